@@ -0,0 +1,237 @@
+use args::ParseError;
+
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+/// How values move between two keyframes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Ease {
+    Linear,
+    /// Starts slowly.
+    In,
+    /// Ends slowly.
+    Out,
+    /// Starts and ends slowly.
+    InOut,
+}
+
+impl FromStr for Ease {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Ease, ParseError> {
+        match s.trim().to_lowercase().as_str() {
+            "linear" => Ok(Ease::Linear),
+            "in" => Ok(Ease::In),
+            "out" => Ok(Ease::Out),
+            "in-out" => Ok(Ease::InOut),
+            _ => Err(format!("Unknown easing: {}", s).into()),
+        }
+    }
+}
+
+impl Ease {
+    /// Eases `t` from 0 to 1.
+    pub fn apply(&self, t: f64) -> f64 {
+        match *self {
+            Ease::Linear => t,
+            Ease::In => t * t,
+            Ease::Out => 1.0 - (1.0 - t) * (1.0 - t),
+            Ease::InOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Options interpolated geometrically, so that zooming runs at a steady rate rather than slowing
+/// down as it goes deeper.
+const GEOMETRIC: [&str; 1] = ["zoom"];
+
+/// The keyframes of a single option.
+#[derive(Debug, Clone)]
+struct Track {
+    name: String,
+    /// Frame, value and the easing into it, in order of frame.
+    keys: Vec<(u32, f64, Ease)>,
+}
+
+impl Track {
+    fn value(&self, frame: u32) -> f64 {
+        let next = match self.keys.iter().position(|&(f, _, _)| f >= frame) {
+            Some(next) => next,
+            None => return self.keys[self.keys.len() - 1].1,
+        };
+
+        let (to_frame, to, ease) = self.keys[next];
+        if next == 0 || to_frame == frame {
+            return to;
+        }
+
+        let (from_frame, from, _) = self.keys[next - 1];
+        let t = ease.apply((frame - from_frame) as f64 / (to_frame - from_frame) as f64);
+
+        if GEOMETRIC.contains(&self.name.as_str()) && from > 0.0 && to > 0.0 {
+            from * (to / from).powf(t)
+        } else {
+            from + (to - from) * t
+        }
+    }
+}
+
+/// Keyframed animation of numeric options.
+///
+/// A keyframe file has one keyframe per line: a frame number, starting from 1, followed by
+/// `name=value` pairs naming options by their long flag, e.g.
+///
+/// ```text
+/// # frame  options
+/// 1        power=2 zoom=350
+/// 60       power=3 zoom=700 ease=in-out
+/// ```
+///
+/// Each option is interpolated between the keyframes that set it, and held before the first and
+/// after the last. `ease` sets the easing of the transition into that keyframe. Zoom is
+/// interpolated geometrically, so an evenly paced zoom scales by the same factor every frame.
+#[derive(Debug, Clone)]
+pub struct Keyframes {
+    tracks: Vec<Track>,
+    frames: u32,
+}
+
+impl FromStr for Keyframes {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Keyframes, ParseError> {
+        let mut tracks: Vec<Track> = vec![];
+        let mut last = 0;
+
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+
+            let frame: u32 = match words.next() {
+                Some(w) => w.parse().map_err(|_| format!("Line {}: invalid frame number: {}", n + 1, w))?,
+                None => continue,
+            };
+
+            if frame <= last {
+                return Err(format!("Line {}: frames must start from 1 and increase", n + 1).into());
+            }
+            last = frame;
+
+            let mut ease = Ease::Linear;
+            let mut values = vec![];
+
+            for word in words {
+                let mut parts = word.splitn(2, '=');
+                let (name, value) = match (parts.next(), parts.next()) {
+                    (Some(name), Some(value)) => (name, value),
+                    _ => return Err(format!("Line {}: expected name=value, got: {}", n + 1, word).into()),
+                };
+
+                if name == "ease" {
+                    ease = value.parse().map_err(|e| format!("Line {}: {}", n + 1, e))?;
+                } else {
+                    let value: f64 = value.parse().map_err(|_| format!("Line {}: invalid value for {}: {}", n + 1, name, value))?;
+                    values.push((name, value));
+                }
+            }
+
+            for (name, value) in values {
+                match tracks.iter().position(|t| t.name == name) {
+                    Some(i) => tracks[i].keys.push((frame, value, ease)),
+                    None => tracks.push(Track {
+                        name: name.to_string(),
+                        keys: vec![(frame, value, ease)],
+                    }),
+                }
+            }
+        }
+
+        if last == 0 {
+            return Err("No keyframes found".to_string().into());
+        }
+
+        Ok(Keyframes {
+            tracks,
+            frames: last,
+        })
+    }
+}
+
+impl Keyframes {
+    pub fn open(path: &str) -> Result<Keyframes, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Unable to read keyframes {}: {}", path, e))?;
+
+        Ok(text.parse()?)
+    }
+
+    /// The number of frames, up to and including the last keyframe.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// The name and interpolated value of every animated option at the given frame.
+    pub fn values(&self, frame: u32) -> Vec<(&str, f64)> {
+        self.tracks.iter().map(|t| (t.name.as_str(), t.value(frame))).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Ease, Keyframes};
+
+    fn value(keyframes: &Keyframes, name: &str, frame: u32) -> f64 {
+        keyframes.values(frame).into_iter().find(|v| v.0 == name).unwrap().1
+    }
+
+    #[test]
+    fn options_are_interpolated_between_keyframes() {
+        let keyframes: Keyframes = "# frame  options\n\
+                                    1   power=2 zoom=100\n\
+                                    \n\
+                                    11  power=4   # halfway\n\
+                                    21  power=3 zoom=300 ease=in\n".parse().unwrap();
+
+        assert_eq!(keyframes.frames(), 21);
+        assert_eq!(value(&keyframes, "power", 1), 2.0);
+        assert_eq!(value(&keyframes, "power", 6), 3.0);
+        assert_eq!(value(&keyframes, "power", 11), 4.0);
+        assert_eq!(value(&keyframes, "power", 16), 4.0 - Ease::In.apply(0.5));
+
+        // Zoom isn't set at frame 11, so it eases all the way from frame 1, geometrically.
+        assert_eq!(value(&keyframes, "zoom", 11), 100.0 * 3.0_f64.powf(Ease::In.apply(0.5)));
+        assert_eq!(value(&keyframes, "zoom", 21), 300.0);
+    }
+
+    #[test]
+    fn zoom_scales_by_the_same_factor_every_frame() {
+        let keyframes: Keyframes = "1 zoom=100 rotate=0\n3 zoom=400 rotate=90".parse().unwrap();
+
+        assert!((value(&keyframes, "zoom", 2) - 200.0).abs() < 1e-9);
+        assert_eq!(value(&keyframes, "rotate", 2), 45.0);
+    }
+
+    #[test]
+    fn options_are_held_outside_their_keyframes() {
+        let keyframes: Keyframes = "1 power=2\n5 zoom=100\n10 power=3".parse().unwrap();
+
+        assert_eq!(value(&keyframes, "zoom", 1), 100.0);
+        assert_eq!(value(&keyframes, "zoom", 10), 100.0);
+        assert_eq!(value(&keyframes, "power", 10), 3.0);
+    }
+
+    #[test]
+    fn invalid_keyframes_are_rejected() {
+        assert!("".parse::<Keyframes>().is_err());
+        assert!("# only a comment".parse::<Keyframes>().is_err());
+        assert!("0 power=2".parse::<Keyframes>().is_err());
+        assert!("2 power=2\n2 power=3".parse::<Keyframes>().is_err());
+        assert!("x power=2".parse::<Keyframes>().is_err());
+        assert!("1 power".parse::<Keyframes>().is_err());
+        assert!("1 power=two".parse::<Keyframes>().is_err());
+        assert!("1 power=2 ease=sideways".parse::<Keyframes>().is_err());
+    }
+}
@@ -0,0 +1,49 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// An invalid option value, with a message saying why.
+///
+/// structopt reports invalid values with `Error::description`, so option types parse into this
+/// rather than a plain `String`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError(pub String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ParseError {
+    fn description(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for ParseError {
+    fn from(s: String) -> ParseError {
+        ParseError(s)
+    }
+}
+
+impl From<ParseError> for String {
+    fn from(e: ParseError) -> String {
+        e.0
+    }
+}
+
+/// A comma-separated list of values, e.g. `5000,500,50`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct List<T>(pub Vec<T>);
+
+impl<T: FromStr> FromStr for List<T> {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<List<T>, ParseError> {
+        s.split(',')
+            .map(|v| v.trim().parse().map_err(|_| ParseError(format!("Invalid list value: {}", v))))
+            .collect::<Result<Vec<_>, _>>()
+            .map(List)
+    }
+}
@@ -0,0 +1,482 @@
+use num::complex::Complex64;
+
+use rayon::prelude::*;
+
+use args::ParseError;
+use viewport::Viewport;
+
+use std::fmt::{self, Debug};
+use std::str::FromStr;
+
+/// A per-channel counter that a canvas accumulates into.
+pub trait Accumulator: Copy + Default + PartialEq + Debug + Send + Sync {
+    fn one() -> Self;
+
+    /// Converts a splat weight, which is only exact for floating-point accumulators.
+    fn from_f64(v: f64) -> Self;
+
+    /// Adds `other`, returning `false` if the result overflowed and was clamped.
+    fn accumulate(&mut self, other: Self) -> bool;
+
+    fn to_f64(self) -> f64;
+
+    /// The accumulator type, as recorded in raw canvas files.
+    fn kind() -> AccumulatorKind;
+
+    /// The bits of the value, for storing in raw canvas files.
+    fn to_raw(self) -> u64;
+
+    fn from_raw(raw: u64) -> Self;
+}
+
+macro_rules! int_accumulator {
+    ($($t:ty => $kind:ident),*) => {$(
+        impl Accumulator for $t {
+            fn one() -> $t {
+                1
+            }
+
+            fn from_f64(v: f64) -> $t {
+                v.round() as $t
+            }
+
+            fn accumulate(&mut self, other: $t) -> bool {
+                match self.checked_add(other) {
+                    Some(v) => {
+                        *self = v;
+                        true
+                    },
+                    None => {
+                        *self = <$t>::max_value();
+                        false
+                    },
+                }
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn kind() -> AccumulatorKind {
+                AccumulatorKind::$kind
+            }
+
+            fn to_raw(self) -> u64 {
+                self as u64
+            }
+
+            fn from_raw(raw: u64) -> $t {
+                raw as $t
+            }
+        }
+    )*}
+}
+
+macro_rules! float_accumulator {
+    ($($t:ty => $kind:ident, $bits:ty),*) => {$(
+        impl Accumulator for $t {
+            fn one() -> $t {
+                1.0
+            }
+
+            fn from_f64(v: f64) -> $t {
+                v as $t
+            }
+
+            /// Floats don't overflow, but stop changing once the sum is too large for the addition to register.
+            fn accumulate(&mut self, other: $t) -> bool {
+                let v = *self + other;
+                let changed = v != *self || other == 0.0;
+                *self = v;
+                changed
+            }
+
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+
+            fn kind() -> AccumulatorKind {
+                AccumulatorKind::$kind
+            }
+
+            fn to_raw(self) -> u64 {
+                self.to_bits() as u64
+            }
+
+            fn from_raw(raw: u64) -> $t {
+                <$t>::from_bits(raw as $bits)
+            }
+        }
+    )*}
+}
+
+int_accumulator!(u16 => U16, u32 => U32, u64 => U64);
+float_accumulator!(f32 => F32, u32, f64 => F64, u64);
+
+/// The accumulator types, as selected on the command line.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AccumulatorKind {
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl FromStr for AccumulatorKind {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<AccumulatorKind, ParseError> {
+        match s.trim().to_lowercase().as_str() {
+            "u16" => Ok(AccumulatorKind::U16),
+            "u32" => Ok(AccumulatorKind::U32),
+            "u64" => Ok(AccumulatorKind::U64),
+            "f32" => Ok(AccumulatorKind::F32),
+            "f64" => Ok(AccumulatorKind::F64),
+            _ => Err(format!("Unknown accumulator: {}", s).into()),
+        }
+    }
+}
+
+impl fmt::Display for AccumulatorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            AccumulatorKind::U16 => "u16",
+            AccumulatorKind::U32 => "u32",
+            AccumulatorKind::U64 => "u64",
+            AccumulatorKind::F32 => "f32",
+            AccumulatorKind::F64 => "f64",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+impl AccumulatorKind {
+    /// Whether the accumulator can hold the fractional weights used for splatting.
+    pub fn is_float(&self) -> bool {
+        *self == AccumulatorKind::F32 || *self == AccumulatorKind::F64
+    }
+
+    /// The size of a value in bytes.
+    pub fn size(&self) -> usize {
+        match *self {
+            AccumulatorKind::U16 => 2,
+            AccumulatorKind::U32 | AccumulatorKind::F32 => 4,
+            AccumulatorKind::U64 | AccumulatorKind::F64 => 8,
+        }
+    }
+}
+
+/// How each plotted point is spread over the pixels around it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Splat {
+    /// The whole point goes to the pixel containing it.
+    None,
+    /// The point is shared between the four nearest pixels by bilinear weights.
+    Bilinear,
+    /// The point is spread by a normalised Gaussian with the given standard deviation in pixels.
+    Gaussian(f64),
+}
+
+impl FromStr for Splat {
+    type Err = ParseError;
+
+    /// Parses a splat name. Gaussian optionally takes its standard deviation after a colon, e.g. `gaussian:0.7`.
+    fn from_str(s: &str) -> Result<Splat, ParseError> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let arg = parts.next();
+
+        match (name.as_str(), arg) {
+            ("none", None) => Ok(Splat::None),
+            ("bilinear", None) => Ok(Splat::Bilinear),
+            ("gaussian", None) => Ok(Splat::Gaussian(0.5)),
+            ("gaussian", Some(a)) => match a.trim().parse() {
+                Ok(sigma) if sigma > 0.0 => Ok(Splat::Gaussian(sigma)),
+                _ => Err(format!("Invalid Gaussian deviation: {}", a).into()),
+            },
+            _ => Err(format!("Unknown splat: {}", s).into()),
+        }
+    }
+}
+
+/// Rows of pixels merged at a time by `Canvas::merge_and_clear`.
+const MERGE_BAND_ROWS: usize = 16;
+
+/// Accumulates orbit densities, with one or more channels per pixel.
+#[derive(Debug, Clone)]
+pub struct Canvas<T> {
+    width: u32,
+    height: u32,
+    channels: usize,
+    data: Vec<T>,
+    overflowed: u64,
+}
+
+impl<T: Accumulator> Canvas<T> {
+    pub fn new(width: u32, height: u32, channels: usize) -> Canvas<T> {
+        Canvas {
+            width,
+            height,
+            channels,
+            data: vec![T::default(); width as usize * height as usize * channels],
+            overflowed: 0,
+        }
+    }
+
+    /// A canvas with the given values, interleaved per pixel.
+    pub fn from_values(width: u32, height: u32, channels: usize, data: Vec<T>, overflowed: u64) -> Canvas<T> {
+        assert_eq!(data.len(), width as usize * height as usize * channels);
+
+        Canvas {
+            width,
+            height,
+            channels,
+            data,
+            overflowed,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// The raw channel values, interleaved per pixel.
+    pub fn values(&self) -> &[T] {
+        &self.data
+    }
+
+    /// The number of additions that overflowed and were clamped.
+    pub fn overflowed(&self) -> u64 {
+        self.overflowed
+    }
+
+    fn index(&self, x: u32, y: u32, channel: usize) -> usize {
+        (y as usize * self.width as usize + x as usize) * self.channels + channel
+    }
+
+    pub fn get(&self, x: u32, y: u32, channel: usize) -> T {
+        self.data[self.index(x, y, channel)]
+    }
+
+    pub fn add(&mut self, x: u32, y: u32, channel: usize) {
+        let idx = self.index(x, y, channel);
+
+        if !self.data[idx].accumulate(T::one()) {
+            self.overflowed += 1;
+        }
+    }
+
+    /// Adds a weight to a pixel, ignoring pixels outside the canvas.
+    pub fn add_weight(&mut self, x: i64, y: i64, channel: usize, weight: f64) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 || weight <= 0.0 {
+            return;
+        }
+
+        let idx = self.index(x as u32, y as u32, channel);
+
+        if !self.data[idx].accumulate(T::from_f64(weight)) {
+            self.overflowed += 1;
+        }
+    }
+
+    /// Adds a point of the given weight at a continuous pixel position, spread according to `splat`.
+    pub fn splat(&mut self, pos_x: f64, pos_y: f64, channel: usize, splat: Splat, weight: f64) {
+        let margin = match splat {
+            Splat::Gaussian(sigma) => 3.0 * sigma + 1.0,
+            _ => 1.0,
+        };
+
+        // Also catches escaped points at infinity or NaN.
+        if !(pos_x > -margin && pos_y > -margin && pos_x < self.width as f64 + margin && pos_y < self.height as f64 + margin) {
+            return;
+        }
+
+        match splat {
+            Splat::None => self.add_weight(pos_x.floor() as i64, pos_y.floor() as i64, channel, weight),
+            Splat::Bilinear => {
+                // Measure from pixel centres.
+                let (fx, fy) = (pos_x - 0.5, pos_y - 0.5);
+                let (x0, y0) = (fx.floor(), fy.floor());
+                let (tx, ty) = (fx - x0, fy - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                self.add_weight(x0, y0, channel, weight * (1.0 - tx) * (1.0 - ty));
+                self.add_weight(x0 + 1, y0, channel, weight * tx * (1.0 - ty));
+                self.add_weight(x0, y0 + 1, channel, weight * (1.0 - tx) * ty);
+                self.add_weight(x0 + 1, y0 + 1, channel, weight * tx * ty);
+            },
+            Splat::Gaussian(sigma) => {
+                let radius = (3.0 * sigma).ceil() as i64;
+                let (cx, cy) = (pos_x.floor() as i64, pos_y.floor() as i64);
+                let mut weights = Vec::with_capacity(((2 * radius + 1) * (2 * radius + 1)) as usize);
+
+                for y in cy - radius..cy + radius + 1 {
+                    for x in cx - radius..cx + radius + 1 {
+                        let dx = x as f64 + 0.5 - pos_x;
+                        let dy = y as f64 + 0.5 - pos_y;
+                        weights.push((x, y, (-(dx * dx + dy * dy) / (2.0 * sigma * sigma)).exp()));
+                    }
+                }
+
+                let total: f64 = weights.iter().map(|&(_, _, w)| w).sum();
+
+                for (x, y, w) in weights {
+                    self.add_weight(x, y, channel, weight * w / total);
+                }
+            },
+        }
+    }
+
+    /// Adds the values of another canvas of the same size into this one.
+    pub fn merge(&mut self, other: &Canvas<T>) {
+        assert_eq!((self.width, self.height, self.channels), (other.width, other.height, other.channels));
+
+        self.overflowed += other.overflowed;
+
+        for (a, &b) in self.data.iter_mut().zip(other.data.iter()) {
+            if !a.accumulate(b) {
+                self.overflowed += 1;
+            }
+        }
+    }
+
+    /// Adds the values of other canvases of the same size into this one and clears them, merging
+    /// bands of rows in parallel.
+    pub fn merge_and_clear(&mut self, others: &mut [Canvas<T>]) {
+        let band = (self.width as usize * self.channels * MERGE_BAND_ROWS).max(1);
+        let mut bands: Vec<Vec<&mut [T]>> = self.data.chunks(band).map(|_| vec![]).collect();
+
+        for other in others.iter_mut() {
+            assert_eq!((self.width, self.height, self.channels), (other.width, other.height, other.channels));
+
+            self.overflowed += other.overflowed;
+            other.overflowed = 0;
+
+            for (sources, chunk) in bands.iter_mut().zip(other.data.chunks_mut(band)) {
+                sources.push(chunk);
+            }
+        }
+
+        let overflowed: u64 = self.data.par_chunks_mut(band).zip(bands.into_par_iter())
+            .map(|(values, sources)| {
+                let mut overflowed = 0;
+
+                for source in sources {
+                    for (a, b) in values.iter_mut().zip(source.iter_mut()) {
+                        if !a.accumulate(*b) {
+                            overflowed += 1;
+                        }
+                        *b = T::default();
+                    }
+                }
+
+                overflowed
+            })
+            .sum();
+
+        self.overflowed += overflowed;
+    }
+
+    /// Plots every point of `points` that lands inside the viewport, with the given weight.
+    pub fn plot(&mut self, view: &Viewport, points: &[Complex64], channel: usize, splat: Splat, weight: f64) {
+        for &z in points {
+            if splat == Splat::None && weight == 1.0 {
+                if let Some((x, y)) = view.project(z) {
+                    self.add(x, y, channel);
+                }
+            } else {
+                let (pos_x, pos_y) = view.position(z);
+                self.splat(pos_x, pos_y, channel, splat, weight);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Canvas, Splat};
+
+    fn splatted(pos_x: f64, pos_y: f64, splat: Splat) -> Canvas<f64> {
+        let mut canvas = Canvas::new(12, 12, 1);
+        canvas.splat(pos_x, pos_y, 0, splat, 2.0);
+        canvas
+    }
+
+    #[test]
+    fn splats_keep_the_weight_of_the_point() {
+        // Away from the edges, where weight falls off the canvas.
+        for &splat in &[Splat::None, Splat::Bilinear, Splat::Gaussian(0.5), Splat::Gaussian(1.3)] {
+            for &(x, y) in &[(5.5, 5.5), (5.2, 6.9), (4.0, 4.0), (6.99, 5.01)] {
+                let total: f64 = splatted(x, y, splat).values().iter().sum();
+                assert!((total - 2.0).abs() < 1e-9, "{:?} at {}, {} adds up to {}", splat, x, y, total);
+            }
+        }
+    }
+
+    #[test]
+    fn points_at_pixel_centres_are_unbiased() {
+        for &splat in &[Splat::None, Splat::Bilinear] {
+            let canvas = splatted(3.5, 4.5, splat);
+            assert_eq!(canvas.get(3, 4, 0), 2.0);
+        }
+
+        let canvas = splatted(3.5, 4.5, Splat::Gaussian(0.8));
+        let centre = canvas.get(3, 4, 0);
+        assert!(canvas.values().iter().all(|&v| v <= centre));
+        assert_eq!(canvas.get(2, 4, 0), canvas.get(4, 4, 0));
+        assert_eq!(canvas.get(3, 3, 0), canvas.get(3, 5, 0));
+        assert_eq!(canvas.get(2, 3, 0), canvas.get(4, 5, 0));
+    }
+
+    #[test]
+    fn overflowing_additions_are_clamped_and_counted() {
+        let mut canvas = Canvas::from_values(2, 1, 1, vec![65534_u16, 0], 0);
+        canvas.add(0, 0, 0);
+        canvas.add(0, 0, 0);
+        canvas.add(0, 0, 0);
+        canvas.add(1, 0, 0);
+
+        assert_eq!(canvas.values(), &[65535, 1]);
+        assert_eq!(canvas.overflowed(), 2);
+
+        let mut floats = Canvas::from_values(1, 1, 1, vec![1e8_f32], 0);
+        floats.add(0, 0, 0);
+        assert_eq!(floats.overflowed(), 1);
+    }
+
+    #[test]
+    fn merging_counts_overflows_of_both_canvases() {
+        let mut canvas = Canvas::from_values(2, 1, 1, vec![40000_u16, 1], 3);
+        canvas.merge(&Canvas::from_values(2, 1, 1, vec![40000, 1], 2));
+
+        assert_eq!(canvas.values(), &[65535, 2]);
+        assert_eq!(canvas.overflowed(), 6);
+
+        let mut others = vec![Canvas::from_values(2, 1, 1, vec![1, 0], 4), Canvas::from_values(2, 1, 1, vec![0, 5], 0)];
+        canvas.merge_and_clear(&mut others);
+
+        assert_eq!(canvas.values(), &[65535, 7]);
+        assert_eq!(canvas.overflowed(), 11);
+        assert!(others.iter().all(|c| c.values() == [0, 0] && c.overflowed() == 0));
+    }
+
+    #[test]
+    fn bilinear_weights_follow_the_position() {
+        // A quarter of a pixel right of the centre of (3, 4).
+        let canvas = splatted(3.75, 4.5, Splat::Bilinear);
+
+        assert_eq!(canvas.get(3, 4, 0), 1.5);
+        assert_eq!(canvas.get(4, 4, 0), 0.5);
+        assert_eq!(canvas.get(3, 5, 0), 0.0);
+    }
+}
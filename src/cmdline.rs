@@ -1,34 +1,38 @@
-extern crate num;
-use num::complex::Complex64;
+extern crate rpixi;
+use rpixi::animate::Keyframes;
+use rpixi::args::List;
+use rpixi::canvas::{Accumulator, AccumulatorKind, Canvas, Splat};
+use rpixi::colouring::{ColourBy, Colouring};
+use rpixi::escape::{EscapeTime, Shading};
+use rpixi::formula::FormulaKind;
+use rpixi::metropolis::Metropolis;
+use rpixi::options::Options;
+use rpixi::orbit::{OrbitMode, Tracer};
+use rpixi::output::{self, Samples};
+use rpixi::overlay::Overlay;
+use rpixi::palette::Palette;
+use rpixi::raw;
+use rpixi::render::{Position, Renderer};
+use rpixi::region::Shape;
+use rpixi::sampler::{SamplerKind, Shard};
+use rpixi::slice::Axis;
+use rpixi::tonemap::{self, Curve};
+use rpixi::viewport::Axes;
 
 extern crate structopt;
 #[macro_use]
 extern crate structopt_derive;
 use structopt::StructOpt;
 
-extern crate image;
-use image::{Rgba, Pixel};
-
-extern crate itertools;
-use itertools::Itertools;
-
-extern crate imageproc;
-use imageproc::drawing::draw_text_mut;
-
-extern crate rusttype;
-use rusttype::{FontCollection, Scale};
-
 extern crate rayon;
-use rayon::prelude::*;
 
 extern crate indicatif;
 use indicatif::{ProgressBar, ProgressStyle};
 
-use std::sync::{Arc, Mutex};
-
-type Pic<T> = image::ImageBuffer<image::Rgba<T>, std::vec::Vec<T>>;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Clone, StructOpt)]
 struct Config {
     #[structopt(short="w", long="width", help="Window width", default_value = "1280")]
     width: u32,
@@ -39,113 +43,486 @@ struct Config {
     #[structopt(short="b", long="bounds", help="Minimum and maximum value for bounds", default_value = "0.6")]
     bounds: f64,
 
+    #[structopt(long="re-min", help="Minimum real value sampled, instead of -bounds")]
+    re_min: Option<f64>,
+
+    #[structopt(long="re-max", help="Maximum real value sampled, instead of bounds")]
+    re_max: Option<f64>,
+
+    #[structopt(long="im-min", help="Minimum imaginary value sampled, instead of -bounds")]
+    im_min: Option<f64>,
+
+    #[structopt(long="im-max", help="Maximum imaginary value sampled, instead of bounds")]
+    im_max: Option<f64>,
+
+    #[structopt(long="region", help="Shape of the sampled region: rect, disc:re,im,r, annulus:re,im,inner,outer or mask:file.png", default_value = "rect")]
+    region: Shape,
+
     #[structopt(short="p", long="power", help="Power to use in the Mandelbrot equation", default_value = "2.0")]
     power: f64,
 
-    #[structopt(short="c", help="Multiplier on the hue", default_value = "0.7")]
+    #[structopt(long="formula", help="Iteration formula: mandelbrot, multibrot, burning-ship, tricorn, celtic, phoenix[:k] or newton", default_value = "mandelbrot")]
+    formula: FormulaKind,
+
+    #[structopt(short="c", long="hue", help="Turns around the hue wheel over the range of --colour-by", default_value = "0.7")]
     colour_factor: f64,
 
     #[structopt(short="o", help="Opacity of the drawn pixel", default_value = "0.8")]
     opacity: f64,
+
+    #[structopt(long="curve", help="Tone curve: linear, log, sqrt, gamma[:g], exp, equalise or clip[:percentile]", default_value = "exp")]
+    curve: Curve,
+
+    #[structopt(long="escape-time", help="Render escape times shaded by smooth or distance instead of orbit densities")]
+    escape_time: Option<Shading>,
+
+    #[structopt(long="palette", help="Colour a single channel with a preset (grey, fire, ice, nebula, viridis) or a .csv or .ggr gradient file")]
+    palette: Option<Palette>,
+
+    #[structopt(long="auto-exposure", help="Map this percentile of lit pixels to white, or near-white with the exp curve, e.g. 99.5")]
+    auto_exposure: Option<f64>,
     
-    #[structopt(short="z", long="zoom", default_value = "350")]
-    zoom: u32,
+    #[structopt(short="z", long="zoom", help="Pixels per unit on the plane", default_value = "350")]
+    zoom: f64,
+
+    #[structopt(long="centre-re", help="Real part of the point at the centre of the image", default_value = "0.0")]
+    centre_re: f64,
+
+    #[structopt(long="centre-im", help="Imaginary part of the point at the centre of the image", default_value = "0.0")]
+    centre_im: f64,
+
+    #[structopt(long="rotate", help="Anticlockwise rotation of the view in degrees", default_value = "0.0")]
+    rotate: f64,
+
+    #[structopt(long="axes", help="Axes running horizontally and vertically: re-im or im-re", default_value = "re-im")]
+    axes: Axes,
 
     #[structopt(short="d", long="delta", help="Steps between each coordinate", default_value = "0.05")]
     delta: f64,
 
+    #[structopt(long="sampler", help="Sample distribution: grid, random, jittered, halton or sobol", default_value = "grid")]
+    sampler: SamplerKind,
+
+    #[structopt(long="samples", help="Number of samples, instead of the number of grid points given by delta")]
+    samples: Option<usize>,
+
+    #[structopt(long="seed", help="Seed for the random samplers", default_value = "0")]
+    seed: usize,
+
+    #[structopt(long="shard", help="Take only this part of the samples and chains, as index/count, so that renders of every part with the same seed merge into the whole", default_value = "1/1")]
+    shard: Shard,
+
+    #[structopt(long="metropolis", help="Number of Metropolis-Hastings mutations to run alongside the sampler; needs a float accumulator")]
+    metropolis: Option<usize>,
+
+    #[structopt(long="chains", help="Number of independent Metropolis-Hastings chains", default_value = "64")]
+    chains: usize,
+
     #[structopt(short="l", long="loops", help="Number of iterations for each coordinate", default_value = "200")]
     loop_limit: u32,
-}
 
-fn mandelbrot(z: Complex64, x: f64, y: f64, cfg: &Config) -> Complex64 {
-    let c = Complex64::new(x, y);
-    z.powf(cfg.power) + c
-}
+    #[structopt(long="bailout", help="Radius beyond which an orbit has escaped", default_value = "2.0")]
+    bailout: f64,
 
-fn draw_point(image: &mut Pic<u16>, z: Complex64, color: Rgba<u16>, cfg: &Config) {
-    let pos_x = (cfg.width as f64/2.0) + z.re * cfg.zoom as f64 - 0.5;
-    let pos_y = (cfg.height as f64/2.0) - z.im * cfg.zoom as f64 + 0.5;
+    #[structopt(long="mode", help="Orbits to plot: buddha (escaping), anti (bounded) or all", default_value = "all")]
+    mode: OrbitMode,
 
-    if pos_x + 1.0 < 0.0 || pos_y < 0.0 {
-        return;
-    }
+    #[structopt(long="skip", help="Iterations at the start of each orbit that are never plotted", default_value = "1")]
+    skip: usize,
 
-    let pos_x = pos_x as u32;
-    let pos_y = pos_y as u32;
+    #[structopt(long="min-iter", help="First plotted point of each orbit, counted after the skipped iterations", default_value = "0")]
+    min_iter: usize,
 
-    if pos_x >= cfg.width || pos_y >= cfg.height {
-        return;
-    }
+    #[structopt(long="max-iter", help="Plotted point of each orbit to stop before, counted after the skipped iterations")]
+    max_iter: Option<usize>,
+
+    #[structopt(long="min-length", help="Drop escaping orbits with fewer plotted points than this", default_value = "0")]
+    min_length: usize,
+
+    #[structopt(long="accumulator", help="Per-pixel counter type: u16, u32, u64, f32 or f64", default_value = "u32")]
+    accumulator: AccumulatorKind,
+
+    #[structopt(long="splat", help="Spread points over nearby pixels: none, bilinear or gaussian[:sigma]; needs a float accumulator", default_value = "none")]
+    splat: Splat,
+
+    #[structopt(long="channels", help="Comma-separated loop limits for red, green and blue, e.g. 5000,500,50")]
+    channels: Option<List<u32>>,
+
+    #[structopt(long="colour-by", help="Colour points by iteration, escape, angle or speed into red, green and blue; needs a float accumulator")]
+    colour_by: Option<ColourBy>,
+
+    #[structopt(long="channel-factors", help="Comma-separated brightness exponents for each channel")]
+    channel_factors: Option<List<f64>>,
+
+    #[structopt(long="re", help="Real part of z0, from the formula's critical point, when it isn't sampled", default_value = "0.0")]
+    off_real: f64,
+
+    #[structopt(long="im", help="Imaginary part of z0, from the formula's critical point, when it isn't sampled", default_value = "0.0")]
+    off_imaginary: f64,
+
+    #[structopt(long="c-re", help="Real part of c when it isn't sampled", default_value = "0.0")]
+    c_re: f64,
+
+    #[structopt(long="c-im", help="Imaginary part of c when it isn't sampled", default_value = "0.0")]
+    c_im: f64,
+
+    #[structopt(long="slice", help="The two sampled axes of (z0, c) space, from z0re, z0im, cre and cim", default_value = "cre,cim")]
+    slice: List<Axis>,
+
+    #[structopt(long="slice-angle", help="Rotation in degrees of the sampled plane towards the fixed axes", default_value = "0.0")]
+    slice_angle: f64,
+
+    #[structopt(long="output", help="Image to save, as .png, .tiff, .ppm or .bmp", default_value = "out.png")]
+    output: String,
+
+    #[structopt(long="bit-depth", help="Bits per sample, 8 or 16; 16-bit images are saved without the text overlay", default_value = "8")]
+    bit_depth: u8,
+
+    #[structopt(long="no-overlay", help="Don't draw the configuration over the image")]
+    no_overlay: bool,
+
+    #[structopt(long="raw", help="Also save the raw canvas to this .rpx file, for re-tone-mapping or merging later")]
+    raw: Option<String>,
+
+    #[structopt(long="checkpoint", help="Save the render in progress to this .rpx file, so it can be resumed with --resume")]
+    checkpoint: Option<String>,
+
+    #[structopt(long="checkpoint-every", help="Seconds between checkpoints", default_value = "600")]
+    checkpoint_every: usize,
 
-    image.get_pixel_mut(pos_x as u32, pos_y as u32).blend(&color);
+    #[structopt(long="resume", help="Continue the render saved in the --checkpoint file, which must have the same settings")]
+    resume: bool,
+
+    #[structopt(long="animate", help="Keyframe file of options to interpolate, rendering each frame to the output numbered, e.g. out_00001.png")]
+    animate: Option<String>,
 }
 
-fn u16_to_u8(from: &image::ImageBuffer<Rgba<u16>, Vec<u16>>, to: &mut image::ImageBuffer<Rgba<u8>, Vec<u8>> ) {
-    for ((_,_,p), (_,_,p2)) in from.enumerate_pixels().zip(to.enumerate_pixels_mut()) {
-        p2[3] = (p[3] >> 8) as u8;
-        p2[2] = (p[2] >> 8) as u8;
-        p2[1] = (p[1] >> 8) as u8;
-        p2[0] = (p[0] >> 8) as u8;
+impl Config {
+    fn options(&self) -> Options {
+        Options {
+            width: self.width,
+            height: self.height,
+            zoom: self.zoom,
+            centre_re: self.centre_re,
+            centre_im: self.centre_im,
+            rotate: self.rotate,
+            axes: self.axes,
+            bounds: self.bounds,
+            re_min: self.re_min,
+            re_max: self.re_max,
+            im_min: self.im_min,
+            im_max: self.im_max,
+            region: self.region.clone(),
+            slice: self.slice.0.clone(),
+            off_real: self.off_real,
+            off_imaginary: self.off_imaginary,
+            c_re: self.c_re,
+            c_im: self.c_im,
+            slice_angle: self.slice_angle,
+            sampler: self.sampler,
+            delta: self.delta,
+            samples: self.samples,
+            seed: self.seed as u64,
+            shard: self.shard,
+            metropolis: self.metropolis,
+            chains: self.chains,
+            loop_limit: self.loop_limit,
+            channels: self.channels.as_ref().map(|l| l.0.clone()),
+            colour_by: self.colour_by,
+            mode: self.mode,
+            min_iter: self.min_iter,
+            max_iter: self.max_iter,
+            min_length: self.min_length,
+            accumulator: self.accumulator,
+            splat: self.splat,
+            factor: tonemap::opacity_factor(self.opacity),
+            channel_factors: self.channel_factors.as_ref().map(|l| l.0.clone()),
+            auto_exposure: self.auto_exposure,
+            palette: self.palette.clone(),
+            output: self.output.clone(),
+            bit_depth: self.bit_depth,
+            checkpoint: self.checkpoint.clone(),
+            resume: self.resume,
+        }
+    }
+
+    /// Sets a numeric option by its long name, for animation.
+    fn set(&mut self, name: &str, v: f64) -> Result<(), String> {
+        match name {
+            "bounds" => self.bounds = v,
+            "re-min" => self.re_min = Some(v),
+            "re-max" => self.re_max = Some(v),
+            "im-min" => self.im_min = Some(v),
+            "im-max" => self.im_max = Some(v),
+            "power" => self.power = v,
+            "hue" => self.colour_factor = v,
+            "opacity" => self.opacity = v,
+            "auto-exposure" => self.auto_exposure = Some(v),
+            "zoom" => self.zoom = v,
+            "centre-re" => self.centre_re = v,
+            "centre-im" => self.centre_im = v,
+            "rotate" => self.rotate = v,
+            "delta" => self.delta = v,
+            "samples" => self.samples = Some(v.round() as usize),
+            "metropolis" => self.metropolis = Some(v.round() as usize),
+            "loops" => self.loop_limit = v.round() as u32,
+            "bailout" => self.bailout = v,
+            "skip" => self.skip = v.round() as usize,
+            "min-iter" => self.min_iter = v.round() as usize,
+            "max-iter" => self.max_iter = Some(v.round() as usize),
+            "min-length" => self.min_length = v.round() as usize,
+            "re" => self.off_real = v,
+            "im" => self.off_imaginary = v,
+            "c-re" => self.c_re = v,
+            "c-im" => self.c_im = v,
+            "slice-angle" => self.slice_angle = v,
+            _ => return Err(format!("Can't animate {}", name)),
+        }
+
+        Ok(())
     }
 }
 
-fn make_frame(cfg: &Config) {
-    let mut pic = Pic::from_pixel(cfg.width, cfg.height, Rgba([0,0,0,u16::max_value()]));
-    let color = Rgba([u16::max_value(), u16::max_value(), u16::max_value(), (u16::max_value() as f64 * cfg.opacity) as u16]);
+fn make_frame<T: Accumulator>(cfg: &Config, path: &str) {
+    let opts = cfg.options();
+    let limits = opts.channel_limits();
 
-    let now = std::time::Instant::now();
-    let cfg_string = format!("{:#?}", cfg);
-    let cfg_string: Vec<_> = cfg_string.lines().enumerate().collect();
+    let formula = cfg.formula.build(cfg.power);
+    let tracer = Tracer {
+        formula: &*formula,
+        slice: opts.slice(),
+        loop_limit: *limits.iter().max().unwrap(),
+        bailout: cfg.bailout,
+        skip: cfg.skip,
+    };
+    let view = opts.view();
 
-    {
-        let shared_pic = Arc::new(Mutex::new(&mut pic));
-        
-        let coords: Vec<_> = (0_u32..).map(|x| -cfg.bounds + x as f64 * cfg.delta).take_while(|&x| x < cfg.bounds).collect();
-        let all_coords: Vec<_> = coords.iter().cartesian_product(coords.iter()).collect();
+    let now = std::time::Instant::now();
 
-        let bar = ProgressBar::new(all_coords.len() as u64);
+    let pic = {
+        let region = opts.region();
+        let sampler = opts.sampler(&region);
+
+        let metropolis = Metropolis {
+            tracer: &tracer,
+            view,
+            region: &region,
+            limits: &limits,
+            filter: opts.filter(),
+            seed: cfg.seed as u64,
+            shard: cfg.shard,
+        };
+        let steps = opts.metropolis_steps();
+        let cancel = AtomicBool::new(false);
+
+        let renderer = Renderer {
+            tracer: &tracer,
+            view,
+            limits: &limits,
+            filter: opts.filter(),
+            splat: cfg.splat,
+            colouring: cfg.colour_by.map(|by| Colouring {
+                by,
+                hue: cfg.colour_factor,
+                limit: limits[0],
+            }),
+            cancel: &cancel,
+        };
+
+        let cfg_string = format!("{:#?}", cfg);
+        let (canvas, position) = match (cfg.resume, cfg.checkpoint.as_ref()) {
+            (true, Some(path)) => raw::resume::<T>(path, &cfg_string).unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }),
+            _ => (Canvas::new(cfg.width, cfg.height, renderer.channels()), Position::default()),
+        };
+
+        let shared_pic = Mutex::new(canvas);
+        let counter = AtomicUsize::new(position.done(cfg.chains, steps));
+        let max = sampler.len() + steps * cfg.chains;
+
+        let bar = ProgressBar::new(max as u64);
         bar.set_style(ProgressStyle::default_bar()
                       .template("[{elapsed_precise}/{eta_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}"));
 
-        all_coords.par_iter()
-            .for_each(|&(&x, &y)| {
-                let mut z = Complex64::new(0.0, 0.0);
-                // Skip the first value to get rid of the square.
-                z = mandelbrot(z, x, y, &cfg)
-                let mut points = vec![];
-
-                for _ in 0..cfg.loop_limit {
-                    z = mandelbrot(z, x, y, &cfg);
-                    points.push(z);
+        let mut last_checkpoint = std::time::Instant::now();
+        let finished = AtomicBool::new(false);
+
+        rayon::join(
+        || {
+            rayon::join(
+                || renderer.render_samples(&sampler, &shared_pic, &counter, &position),
+                || renderer.render_metropolis(&metropolis, cfg.chains, steps, &shared_pic, &counter, &position));
+            finished.store(true, Ordering::SeqCst);
+        },
+        || loop {
+            // Read before the counter, so the last position shown is the final one.
+            let finished = finished.load(Ordering::SeqCst);
+            bar.set_position(counter.load(Ordering::SeqCst) as u64);
+
+            if finished {
+                break;
+            }
+
+            if let Some(ref path) = cfg.checkpoint {
+                if last_checkpoint.elapsed().as_secs() >= cfg.checkpoint_every as u64 {
+                    match raw::save_checkpoint(path, &shared_pic, &position, &cfg_string) {
+                        Ok(()) => bar.set_message(&format!("checkpoint saved to {}", path)),
+                        Err(e) => bar.set_message(&e),
+                    }
+                    last_checkpoint = std::time::Instant::now();
                 }
-                
-                let mut pic = shared_pic.lock().expect("Unlock failed");
+            }
 
-                for z in points {
-                    draw_point(&mut pic, z, color, &cfg);
-                }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        });
 
-                bar.inc(1);
-            });
-        
         bar.finish();
+        shared_pic.into_inner().expect("Lock failed")
+    };
+
+    let mut status = format!("{}s", now.elapsed().as_secs());
+    if pic.overflowed() > 0 {
+        eprintln!("Warning: {} additions overflowed the accumulator, try a wider --accumulator.", pic.overflowed());
+        status += &format!(" - {} overflowed", pic.overflowed());
+    }
+
+    if let Some(ref path) = cfg.raw {
+        if let Err(e) = raw::save(path, &pic, &format!("{:#?}", cfg)) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+
+    let factors = opts.exposed_factors(&pic);
+    if cfg.auto_exposure.is_some() && cfg.curve == Curve::Exp {
+        println!("Factors: {:?}", factors);
     }
+    let rgb = tonemap::map(&pic, cfg.curve, &factors, cfg.auto_exposure, 1.0, cfg.palette.as_ref());
+    save(cfg, &rgb, &status, path);
+}
+
+fn make_escape_frame(cfg: &Config, shading: Shading, path: &str) {
+    let opts = cfg.options();
+    let formula = cfg.formula.build(cfg.power);
+    let escape = EscapeTime {
+        formula: &*formula,
+        slice: opts.slice(),
+        power: cfg.power,
+        loop_limit: *opts.channel_limits().iter().max().unwrap(),
+        bailout: cfg.bailout,
+    };
+
+    let now = std::time::Instant::now();
+    let rgb = escape.map(&opts.view(), shading, cfg.palette.as_ref());
+
+    save(cfg, &rgb, &format!("{}s", now.elapsed().as_secs()), path);
+}
 
-    let font = Vec::from(include_bytes!("SourceSansPro-Light.ttf") as &[u8]);
-    let font = FontCollection::from_bytes(font).into_font().unwrap();
-    let scale = Scale { x: 12.4 * 2.0, y: 12.4 };
-    let mut buffer = Pic::from_pixel(cfg.width, cfg.height, Rgba([0,0,0,u8::max_value()]));
-    u16_to_u8(&pic, &mut buffer);
-    draw_text_mut(&mut buffer, Rgba([255, 255, 255, 255]), 10, 10, scale, &font, &format!("{}s", now.elapsed().as_secs()));
+/// Saves the tone-mapped image, drawing the status and configuration over 8-bit images unless
+/// `--no-overlay` is given.
+fn save(cfg: &Config, rgb: &[[f64; 3]], status: &str, path: &str) {
+    let res = if cfg.bit_depth == 16 {
+        output::save(path, cfg.width, cfg.height, Samples::U16(&output::to_u16(rgb)))
+    } else {
+        let mut buffer = output::to_image(cfg.width, cfg.height, rgb);
+        if !cfg.no_overlay {
+            Overlay::new().draw(&mut buffer, status, &format!("{:#?}", cfg));
+        }
+        output::save(path, cfg.width, cfg.height, Samples::U8(&output::rgb_bytes(&buffer)))
+    };
+
+    if let Err(e) = res {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Checks the options fit together, returning the problem if not.
+fn check(cfg: &Config) -> Result<(), String> {
+    cfg.options().check()?;
 
-    for &(i, l) in cfg_string.iter() {
-        draw_text_mut(&mut buffer, Rgba([255, 255, 255, 255]), 10, 20 + i as u32*13, scale, &font, l);
+    if (cfg.raw.is_some() || cfg.checkpoint.is_some()) && cfg.escape_time.is_some() {
+        return Err("Escape-time renders have no raw canvas to save.".to_string());
     }
-    let _ = buffer.save("out.png").unwrap();
+
+    if cfg.checkpoint.is_some() && cfg.animate.is_some() {
+        return Err("Animations can't be checkpointed.".to_string());
+    }
+
+    Ok(())
+}
+
+fn render(cfg: &Config, path: &str) {
+    if let Some(shading) = cfg.escape_time {
+        return make_escape_frame(cfg, shading, path);
+    }
+
+    match cfg.accumulator {
+        AccumulatorKind::U16 => make_frame::<u16>(cfg, path),
+        AccumulatorKind::U32 => make_frame::<u32>(cfg, path),
+        AccumulatorKind::U64 => make_frame::<u64>(cfg, path),
+        AccumulatorKind::F32 => make_frame::<f32>(cfg, path),
+        AccumulatorKind::F64 => make_frame::<f64>(cfg, path),
+    }
+}
+
+/// Renders every frame of the keyframe file, checking every frame's options before starting.
+fn animate(cfg: &Config, keyframes: &str) -> Result<(), String> {
+    let keyframes = Keyframes::open(keyframes)?;
+
+    let frames = (1..keyframes.frames() + 1).map(|frame| {
+        let mut frame_cfg = cfg.clone();
+        for (name, value) in keyframes.values(frame) {
+            frame_cfg.set(name, value)?;
+        }
+        frame_cfg.raw = cfg.raw.as_ref().map(|path| output::with_suffix(path, &format!("{:05}", frame)));
+
+        check(&frame_cfg).map_err(|e| format!("Frame {}: {}", frame, e))?;
+        Ok(frame_cfg)
+    }).collect::<Result<Vec<_>, String>>()?;
+
+    for (i, frame_cfg) in frames.iter().enumerate() {
+        println!("Frame {}/{}", i + 1, frames.len());
+        render(frame_cfg, &output::with_suffix(&cfg.output, &format!("{:05}", i + 1)));
+    }
+
+    Ok(())
 }
 
 fn main() {
     let cfg = Config::from_args();
-    make_frame(&cfg);
+
+    let res = match cfg.animate {
+        Some(ref keyframes) => animate(&cfg, keyframes),
+        None => check(&cfg).map(|_| render(&cfg, &cfg.output)),
+    };
+
+    if let Err(e) = res {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use structopt::StructOpt;
+
+    fn parse(args: &[&str]) -> Config {
+        Config::from_clap(Config::clap().get_matches_from(args))
+    }
+
+    #[test]
+    fn checkpoint_interval_takes_a_value() {
+        assert_eq!(parse(&["cmdline"]).checkpoint_every, 600);
+        assert_eq!(parse(&["cmdline", "--checkpoint-every", "30"]).checkpoint_every, 30);
+    }
+
+    #[test]
+    fn seed_takes_a_value() {
+        assert_eq!(parse(&["cmdline"]).seed, 0);
+        assert_eq!(parse(&["cmdline", "--seed", "42"]).seed, 42);
+    }
 }
@@ -0,0 +1,93 @@
+use num::complex::Complex64;
+
+use args::ParseError;
+
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+/// The orbit property that sets the hue of each plotted point.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ColourBy {
+    /// The point's iteration number.
+    Iteration,
+    /// The iteration the whole orbit escaped at, with bounded orbits at the loop limit.
+    Escape,
+    /// The angle of z.
+    Angle,
+    /// The distance to the next point of the orbit.
+    Speed,
+}
+
+impl FromStr for ColourBy {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<ColourBy, ParseError> {
+        match s.trim().to_lowercase().as_str() {
+            "iteration" => Ok(ColourBy::Iteration),
+            "escape" => Ok(ColourBy::Escape),
+            "angle" => Ok(ColourBy::Angle),
+            "speed" => Ok(ColourBy::Speed),
+            _ => Err(format!("Unknown colouring: {}", s).into()),
+        }
+    }
+}
+
+/// Colours orbit points around the hue wheel, for accumulating into red, green and blue channels.
+#[derive(Debug, Copy, Clone)]
+pub struct Colouring {
+    pub by: ColourBy,
+    /// Turns around the hue wheel over the full range of the property.
+    pub hue: f64,
+    pub limit: u32,
+}
+
+impl Colouring {
+    /// Where point `n` of the orbit lies in the range of the property, from 0 to 1.
+    fn position(&self, points: &[Complex64], n: usize, escaped: Option<usize>) -> f64 {
+        match self.by {
+            ColourBy::Iteration => n as f64 / self.limit as f64,
+            ColourBy::Escape => match escaped {
+                Some(len) => len as f64 / self.limit as f64,
+                None => 1.0,
+            },
+            ColourBy::Angle => (points[n].arg() + PI) / (2.0 * PI),
+            ColourBy::Speed => {
+                let speed = if n + 1 < points.len() {
+                    (points[n + 1] - points[n]).norm()
+                } else if n > 0 {
+                    (points[n] - points[n - 1]).norm()
+                } else {
+                    0.0
+                };
+
+                // Points next to an escape to infinity get the end of the range.
+                if speed.is_finite() { speed / (1.0 + speed) } else { 1.0 }
+            },
+        }
+    }
+
+    /// The colour of point `n` of the orbit.
+    pub fn colour(&self, points: &[Complex64], n: usize, escaped: Option<usize>) -> [f64; 3] {
+        let hue = (self.position(points, n, escaped) * self.hue * 360.0) % 360.0;
+        hsv_to_rgb(if hue < 0.0 { hue + 360.0 } else { hue }, 1.0, 1.0)
+    }
+}
+
+/// Converts a hue in degrees, and saturation and value from 0 to 1, to red, green and blue from 0 to 1.
+pub fn hsv_to_rgb(hue: f64, sat: f64, val: f64) -> [f64; 3] {
+    let hi = (hue/60.0).floor() % 6.0;
+    let f = (hue/60.0) - (hue/60.0).floor();
+    let p = val * (1.0 - sat);
+    let q = val * (1.0 - f * sat);
+    let t = val * (1.0 - (1.0-f) * sat);
+
+    match hi as u8 {
+        0 => [val, t, p],
+        1 => [q, val, p],
+        2 => [p, val, t],
+        3 => [p, q, val],
+        4 => [t, p, val],
+        5 => [val, p, q],
+        _ => [0.0, 0.0, 0.0]
+    }
+}
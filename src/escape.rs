@@ -0,0 +1,162 @@
+use image::{ImageBuffer, Rgba};
+use num::complex::Complex64;
+use rayon::prelude::*;
+
+use args::ParseError;
+use formula::Formula;
+use palette::Palette;
+use slice::Slice;
+use viewport::Viewport;
+
+use std::str::FromStr;
+
+/// Points within this squared distance of an earlier point in their orbit are taken to be periodic.
+const PERIODICITY_EPSILON: f64 = 1e-20;
+
+/// The smallest bailout used for smooth iteration counts, which need a large radius to avoid banding.
+const SMOOTH_BAILOUT: f64 = 256.0;
+
+/// How escaping points are shaded by the escape-time renderer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Shading {
+    /// The continuous iteration count at which the point escaped.
+    Smooth,
+    /// The estimated distance to the set, in pixels. Formulas without a derivative use smooth counts instead.
+    Distance,
+}
+
+impl FromStr for Shading {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Shading, ParseError> {
+        match s.trim().to_lowercase().as_str() {
+            "smooth" => Ok(Shading::Smooth),
+            "distance" => Ok(Shading::Distance),
+            _ => Err(format!("Unknown shading: {}", s).into()),
+        }
+    }
+}
+
+/// The result of iterating a single point.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Escape {
+    /// The orbit stayed bounded, or was found to be periodic.
+    Interior,
+    Escaped {
+        /// The continuous iteration count.
+        smooth: f64,
+        /// The estimated distance to the set, if the formula has a derivative.
+        distance: Option<f64>,
+    },
+}
+
+/// The classic escape-time renderer, colouring each pixel by how its point escapes.
+pub struct EscapeTime<'a> {
+    pub formula: &'a dyn Formula,
+    pub slice: Slice,
+    pub power: f64,
+    pub loop_limit: u32,
+    pub bailout: f64,
+}
+
+impl<'a> EscapeTime<'a> {
+    /// Iterates the sample point `s`, which is mapped onto the slice.
+    pub fn escape(&self, s: Complex64) -> Escape {
+        let bailout = self.bailout.max(SMOOTH_BAILOUT);
+        let (z0, c) = self.slice.point(s);
+        let mut z = z0 + self.formula.critical_point();
+        let mut prev = Complex64::new(0.0, 0.0);
+        let (mut dz, dc, mut has_derivative) = match self.slice.derivatives() {
+            Some((dz0, dc)) => (dz0, dc, true),
+            None => (Complex64::new(0.0, 0.0), Complex64::new(0.0, 0.0), false),
+        };
+
+        // Brent's cycle detection: compare against a saved point, saved again at each power of two.
+        let mut saved = z;
+        let mut period = 1;
+
+        for i in 0..self.loop_limit {
+            if has_derivative {
+                match self.formula.derivative(z, dz, dc) {
+                    Some(d) => dz = d,
+                    None => has_derivative = false,
+                }
+            }
+
+            let next = self.formula.next(z, prev, c);
+            prev = z;
+            z = next;
+
+            if self.formula.escaped(z, prev, bailout) {
+                let r = z.norm();
+                let smooth = (i + 1) as f64 + 1.0 - r.ln().ln() / self.power.ln();
+
+                let distance = if has_derivative && dz.norm() > 0.0 {
+                    Some(r * r.ln() / dz.norm())
+                } else {
+                    None
+                };
+
+                return Escape::Escaped {
+                    smooth: if smooth.is_finite() { smooth.max(0.0) } else { (i + 1) as f64 },
+                    distance,
+                };
+            }
+
+            if (z - saved).norm_sqr() < PERIODICITY_EPSILON {
+                return Escape::Interior;
+            }
+
+            if i + 1 == period {
+                saved = z;
+                period *= 2;
+            }
+        }
+
+        Escape::Interior
+    }
+
+    /// The brightness of an escaped point, from 0 to 1.
+    fn brightness(&self, escape: Escape, shading: Shading, zoom: f64) -> Option<f64> {
+        match escape {
+            Escape::Interior => None,
+            Escape::Escaped { distance: Some(d), .. } if shading == Shading::Distance => Some((d * zoom).sqrt().min(1.0)),
+            Escape::Escaped { smooth, .. } => Some((smooth + 1.0).ln() / (self.loop_limit as f64 + 1.0).ln()),
+        }
+    }
+
+    /// Maps the viewport to red, green and blue brightness from 0 to 1, in row order, with interior
+    /// points black.
+    ///
+    /// Escaped points are coloured with `palette`, or greyscale without one.
+    pub fn map(&self, view: &Viewport, shading: Shading, palette: Option<&Palette>) -> Vec<[f64; 3]> {
+        let width = view.width as usize;
+        let mut rgb = vec![[0.0; 3]; width * view.height as usize];
+
+        rgb.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+            for (x, px) in row.iter_mut().enumerate() {
+                let escape = self.escape(view.unproject(x as u32, y as u32));
+
+                if let Some(t) = self.brightness(escape, shading, view.zoom) {
+                    *px = match palette {
+                        Some(palette) => palette.colour(t),
+                        None => [t; 3],
+                    };
+                }
+            }
+        });
+
+        rgb
+    }
+
+    /// Renders the viewport into an image, with interior points black.
+    ///
+    /// Escaped points are coloured with `palette`, or drawn in greyscale without one.
+    pub fn draw(&self, view: &Viewport, shading: Shading, palette: Option<&Palette>, to: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+        let rgb = self.map(view, shading, palette);
+
+        for (p2, px) in to.pixels_mut().zip(rgb.iter()) {
+            *p2 = Rgba([(px[0].min(1.0) * 255.0) as u8, (px[1].min(1.0) * 255.0) as u8, (px[2].min(1.0) * 255.0) as u8, 255]);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use num::complex::Complex64;
+
+use args::ParseError;
+
+use std::str::FromStr;
+
+/// An iteration formula, computing the next point of an orbit.
+///
+/// `prev` is the point before `z`, which is only used by formulas with memory such as Phoenix.
+pub trait Formula: Sync + Send {
+    fn next(&self, z: Complex64, prev: Complex64, c: Complex64) -> Complex64;
+
+    /// The critical point orbits start from, which z0 is an offset from.
+    fn critical_point(&self) -> Complex64 {
+        Complex64::new(0.0, 0.0)
+    }
+
+    /// Whether the orbit has escaped after reaching `z` from `prev`.
+    fn escaped(&self, z: Complex64, _prev: Complex64, bailout: f64) -> bool {
+        let r = z.norm_sqr();
+        r > bailout * bailout || r.is_nan()
+    }
+
+    /// The derivative of the next point, given `dz` and `dc`, the derivatives of `z` and `c`.
+    ///
+    /// Used for distance estimation, and `None` for formulas without a simple derivative.
+    fn derivative(&self, _z: Complex64, _dz: Complex64, _dc: Complex64) -> Option<Complex64> {
+        None
+    }
+}
+
+/// z^p + c, using a floating-point power.
+pub struct Mandelbrot {
+    pub power: f64,
+}
+
+impl Formula for Mandelbrot {
+    fn next(&self, z: Complex64, _: Complex64, c: Complex64) -> Complex64 {
+        z.powf(self.power) + c
+    }
+
+    fn derivative(&self, z: Complex64, dz: Complex64, dc: Complex64) -> Option<Complex64> {
+        Some(z.powf(self.power - 1.0) * self.power * dz + dc)
+    }
+}
+
+/// z^d + c, rounding the power to an integer and using repeated multiplication.
+pub struct Multibrot {
+    pub power: i32,
+}
+
+impl Formula for Multibrot {
+    fn next(&self, z: Complex64, _: Complex64, c: Complex64) -> Complex64 {
+        powi(z, self.power) + c
+    }
+
+    fn derivative(&self, z: Complex64, dz: Complex64, dc: Complex64) -> Option<Complex64> {
+        Some(powi(z, self.power - 1) * self.power as f64 * dz + dc)
+    }
+}
+
+/// (|re| + i|im|)^p + c
+pub struct BurningShip {
+    pub power: f64,
+}
+
+impl Formula for BurningShip {
+    fn next(&self, z: Complex64, _: Complex64, c: Complex64) -> Complex64 {
+        Complex64::new(z.re.abs(), z.im.abs()).powf(self.power) + c
+    }
+}
+
+/// conj(z)^p + c, also known as the Mandelbar.
+pub struct Tricorn {
+    pub power: f64,
+}
+
+impl Formula for Tricorn {
+    fn next(&self, z: Complex64, _: Complex64, c: Complex64) -> Complex64 {
+        z.conj().powf(self.power) + c
+    }
+}
+
+/// z^p with the absolute value taken of the real part, + c.
+pub struct Celtic {
+    pub power: f64,
+}
+
+impl Formula for Celtic {
+    fn next(&self, z: Complex64, _: Complex64, c: Complex64) -> Complex64 {
+        let w = z.powf(self.power);
+        Complex64::new(w.re.abs(), w.im) + c
+    }
+}
+
+/// z^p + c + k * z_{n-1}
+pub struct Phoenix {
+    pub power: f64,
+    pub k: f64,
+}
+
+impl Formula for Phoenix {
+    fn next(&self, z: Complex64, prev: Complex64, c: Complex64) -> Complex64 {
+        z.powf(self.power) + c + prev * self.k
+    }
+}
+
+/// Newton's method for z^p - 1 = 0, offset by c each step (the Nova fractal).
+pub struct Newton {
+    pub power: f64,
+}
+
+impl Formula for Newton {
+    fn next(&self, z: Complex64, _: Complex64, c: Complex64) -> Complex64 {
+        let one = Complex64::new(1.0, 0.0);
+        let dz = z.powf(self.power - 1.0) * self.power;
+        z - (z.powf(self.power) - one) / dz + c
+    }
+
+    /// Orbits start at 1, as the Newton step is undefined at 0 where the derivative vanishes.
+    fn critical_point(&self) -> Complex64 {
+        Complex64::new(1.0, 0.0)
+    }
+
+    /// Newton orbits converge rather than diverge, so convergence is treated as escaping.
+    /// The bailout is ignored.
+    fn escaped(&self, z: Complex64, prev: Complex64, _: f64) -> bool {
+        let d = (z - prev).norm_sqr();
+        d < NEWTON_EPSILON || d.is_nan()
+    }
+}
+
+const NEWTON_EPSILON: f64 = 1e-12;
+
+fn powi(z: Complex64, power: i32) -> Complex64 {
+    let mut base = if power < 0 { z.inv() } else { z };
+    let mut exp = power.abs();
+    let mut res = Complex64::new(1.0, 0.0);
+
+    while exp > 0 {
+        if exp & 1 == 1 {
+            res *= base;
+        }
+        base = base * base;
+        exp >>= 1;
+    }
+
+    res
+}
+
+/// The built-in formulas, as selected on the command line.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum FormulaKind {
+    Mandelbrot,
+    Multibrot,
+    BurningShip,
+    Tricorn,
+    Celtic,
+    Phoenix(f64),
+    Newton,
+}
+
+impl FormulaKind {
+    pub fn build(&self, power: f64) -> Box<dyn Formula> {
+        match *self {
+            FormulaKind::Mandelbrot => Box::new(Mandelbrot { power }),
+            FormulaKind::Multibrot => Box::new(Multibrot { power: power.round() as i32 }),
+            FormulaKind::BurningShip => Box::new(BurningShip { power }),
+            FormulaKind::Tricorn => Box::new(Tricorn { power }),
+            FormulaKind::Celtic => Box::new(Celtic { power }),
+            FormulaKind::Phoenix(k) => Box::new(Phoenix { power, k }),
+            FormulaKind::Newton => Box::new(Newton { power }),
+        }
+    }
+}
+
+impl FromStr for FormulaKind {
+    type Err = ParseError;
+
+    /// Parses a formula name. Phoenix optionally takes its coefficient after a colon, e.g. `phoenix:-0.5`.
+    fn from_str(s: &str) -> Result<FormulaKind, ParseError> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let arg = parts.next();
+
+        let kind = match name.as_str() {
+            "mandelbrot" => FormulaKind::Mandelbrot,
+            "multibrot" => FormulaKind::Multibrot,
+            "burning-ship" | "burningship" => FormulaKind::BurningShip,
+            "tricorn" | "mandelbar" => FormulaKind::Tricorn,
+            "celtic" => FormulaKind::Celtic,
+            "phoenix" => {
+                let k = match arg {
+                    Some(a) => a.trim().parse().map_err(|_| format!("Invalid Phoenix coefficient: {}", a))?,
+                    None => -0.5,
+                };
+                return Ok(FormulaKind::Phoenix(k));
+            },
+            "newton" | "nova" => FormulaKind::Newton,
+            _ => return Err(format!("Unknown formula: {}", s).into()),
+        };
+
+        if arg.is_some() {
+            return Err(format!("Formula {} takes no argument", name).into());
+        }
+
+        Ok(kind)
+    }
+}
+
+/// Iterator over the successive points of an orbit, starting after `z0`.
+pub struct Orbit<'a> {
+    formula: &'a dyn Formula,
+    z: Complex64,
+    prev: Complex64,
+    c: Complex64,
+}
+
+impl<'a> Orbit<'a> {
+    pub fn new(formula: &'a dyn Formula, z0: Complex64, c: Complex64) -> Orbit<'a> {
+        Orbit {
+            formula,
+            z: z0,
+            prev: Complex64::new(0.0, 0.0),
+            c,
+        }
+    }
+}
+
+impl<'a> Iterator for Orbit<'a> {
+    type Item = Complex64;
+
+    fn next(&mut self) -> Option<Complex64> {
+        let next = self.formula.next(self.z, self.prev, self.c);
+        self.prev = self.z;
+        self.z = next;
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Complex64, b: Complex64) -> bool {
+        (a - b).norm() < 1e-9
+    }
+
+    #[test]
+    fn variants_fold_the_plane_before_squaring() {
+        let (z, zero, c) = (Complex64::new(-1.0, -2.0), Complex64::new(0.0, 0.0), Complex64::new(0.5, 0.0));
+
+        assert!(close(Mandelbrot { power: 2.0 }.next(z, zero, c), Complex64::new(-2.5, 4.0)));
+        assert!(close(BurningShip { power: 2.0 }.next(z, zero, c), Complex64::new(-2.5, 4.0)));
+        assert!(close(Tricorn { power: 2.0 }.next(z, zero, c), Complex64::new(-2.5, -4.0)));
+        assert!(close(Celtic { power: 2.0 }.next(z, zero, c), Complex64::new(3.5, 4.0)));
+        assert!(close(Multibrot { power: 3 }.next(z, zero, c), z * z * z + c));
+        assert!(close(powi(z, -2), (z * z).inv()));
+    }
+
+    #[test]
+    fn phoenix_adds_the_previous_point() {
+        let phoenix = Phoenix { power: 2.0, k: -0.5 };
+        let orbit: Vec<_> = Orbit::new(&phoenix, Complex64::new(0.0, 0.0), Complex64::new(0.25, 0.0)).take(3).collect();
+
+        assert!(close(orbit[0], Complex64::new(0.25, 0.0)));
+        assert!(close(orbit[1], Complex64::new(0.3125, 0.0)));
+        assert!(close(orbit[2], Complex64::new(0.3125 * 0.3125 + 0.25 - 0.125, 0.0)));
+    }
+
+    #[test]
+    fn newton_orbits_escape_by_converging() {
+        let newton = Newton { power: 3.0 };
+        assert_eq!(newton.critical_point(), Complex64::new(1.0, 0.0));
+
+        let mut prev = Complex64::new(2.0, 1.0);
+        let mut orbit = Orbit::new(&newton, prev, Complex64::new(0.0, 0.0));
+        let converged = (0..100).map(|_| orbit.next().unwrap()).position(|z| {
+            let escaped = newton.escaped(z, prev, 2.0);
+            prev = z;
+            escaped
+        });
+
+        assert!(converged.is_some());
+        assert!(close(powi(prev, 3), Complex64::new(1.0, 0.0)));
+    }
+
+    #[test]
+    fn orbits_escape_past_the_bailout() {
+        let mandelbrot = Mandelbrot { power: 2.0 };
+        let zero = Complex64::new(0.0, 0.0);
+
+        assert_eq!(mandelbrot.critical_point(), zero);
+        assert!(!mandelbrot.escaped(Complex64::new(1.5, 1.5), zero, 2.2));
+        assert!(mandelbrot.escaped(Complex64::new(1.6, 1.6), zero, 2.2));
+        assert!(mandelbrot.escaped(Complex64::new(f64::NAN, 0.0), zero, 2.2));
+    }
+
+    #[test]
+    fn formulas_are_parsed() {
+        assert_eq!("mandelbar".parse(), Ok(FormulaKind::Tricorn));
+        assert_eq!("phoenix".parse(), Ok(FormulaKind::Phoenix(-0.5)));
+        assert_eq!("phoenix:0.3".parse(), Ok(FormulaKind::Phoenix(0.3)));
+        assert!("celtic:1".parse::<FormulaKind>().is_err());
+        assert!("julia".parse::<FormulaKind>().is_err());
+    }
+}
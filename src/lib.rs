@@ -0,0 +1,25 @@
+extern crate image;
+extern crate imageproc;
+extern crate num;
+extern crate rayon;
+extern crate rusttype;
+
+pub mod animate;
+pub mod args;
+pub mod canvas;
+pub mod colouring;
+pub mod escape;
+pub mod formula;
+pub mod metropolis;
+pub mod options;
+pub mod orbit;
+pub mod output;
+pub mod overlay;
+pub mod palette;
+pub mod raw;
+pub mod region;
+pub mod render;
+pub mod sampler;
+pub mod slice;
+pub mod tonemap;
+pub mod viewport;
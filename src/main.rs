@@ -1,5 +1,22 @@
-extern crate num;
-use num::complex::Complex64;
+extern crate rpixi;
+use rpixi::args::List;
+use rpixi::canvas::{Accumulator, AccumulatorKind, Canvas, Splat};
+use rpixi::colouring::{ColourBy, Colouring};
+use rpixi::escape::{EscapeTime, Shading};
+use rpixi::formula::FormulaKind;
+use rpixi::metropolis::Metropolis;
+use rpixi::options::Options;
+use rpixi::orbit::{OrbitMode, Tracer};
+use rpixi::output::{self, Naming, Samples};
+use rpixi::overlay::Overlay;
+use rpixi::palette::Palette;
+use rpixi::raw;
+use rpixi::render::{Position, Renderer};
+use rpixi::region::Shape;
+use rpixi::sampler::{SamplerKind, Shard};
+use rpixi::slice::Axis;
+use rpixi::tonemap::{self, Curve};
+use rpixi::viewport::{Axes, Viewport};
 
 extern crate piston_window;
 use piston_window::*;
@@ -10,27 +27,14 @@ extern crate structopt_derive;
 use structopt::StructOpt;
 
 extern crate image;
-use image::{LumaA, Rgba};
-
-extern crate imageproc;
-use imageproc::drawing::draw_text_mut;
-
-extern crate rusttype;
-use rusttype::{FontCollection, Scale};
+use image::Rgba;
 
 extern crate rayon;
-use rayon::prelude::*;
-
-extern crate itertools;
-use itertools::Itertools;
 
 use std::sync::{Arc, Mutex};
-use std::ops::DerefMut;
-
-type Pic<T> = image::ImageBuffer<image::LumaA<T>, std::vec::Vec<T>>;
-type SharedData<'a> = Arc<Mutex<(&'a mut Pic<u16>, &'a mut State)>>;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-#[derive(Debug, StructOpt)]
+#[derive(Debug, Clone, StructOpt)]
 struct Config {
     #[structopt(short="w", long="width", help="Window width", default_value = "1280")]
     width: u32,
@@ -41,126 +45,293 @@ struct Config {
     #[structopt(short="b", long="bounds", help="Minimum and maximum value for bounds", default_value = "0.6")]
     bounds: f64,
 
+    #[structopt(long="re-min", help="Minimum real value sampled, instead of -bounds")]
+    re_min: Option<f64>,
+
+    #[structopt(long="re-max", help="Maximum real value sampled, instead of bounds")]
+    re_max: Option<f64>,
+
+    #[structopt(long="im-min", help="Minimum imaginary value sampled, instead of -bounds")]
+    im_min: Option<f64>,
+
+    #[structopt(long="im-max", help="Maximum imaginary value sampled, instead of bounds")]
+    im_max: Option<f64>,
+
+    #[structopt(long="region", help="Shape of the sampled region: rect, disc:re,im,r, annulus:re,im,inner,outer or mask:file.png", default_value = "rect")]
+    region: Shape,
+
     #[structopt(short="p", long="power", help="Power to use in the Mandelbrot equation", default_value = "2.0")]
     power: f64,
 
-    #[structopt(short="f", long="factor", help="Exponent used to determine brightness curve", default_value = "50.0")]
+    #[structopt(long="formula", help="Iteration formula: mandelbrot, multibrot, burning-ship, tricorn, celtic, phoenix[:k] or newton", default_value = "mandelbrot")]
+    formula: FormulaKind,
+
+    #[structopt(short="f", long="factor", help="Exponent used to determine brightness of the exp curve", default_value = "50.0")]
     factor: f64,
+
+    #[structopt(short="c", long="hue", help="Turns around the hue wheel over the range of --colour-by", default_value = "0.7")]
+    colour_factor: f64,
+
+    #[structopt(long="curve", help="Tone curve: linear, log, sqrt, gamma[:g], exp, equalise or clip[:percentile]", default_value = "exp")]
+    curve: Curve,
+
+    #[structopt(long="escape-time", help="Shading of the escape-time inset toggled with E: smooth or distance", default_value = "smooth")]
+    escape_time: Shading,
+
+    #[structopt(long="palette", help="Colour a single channel with a preset (grey, fire, ice, nebula, viridis) or a .csv or .ggr gradient file")]
+    palette: Option<Palette>,
+
+    #[structopt(long="auto-exposure", help="Map this percentile of lit pixels to white, or near-white with the exp curve, e.g. 99.5")]
+    auto_exposure: Option<f64>,
     
-    #[structopt(short="z", long="zoom", default_value = "350")]
-    zoom: u32,
+    #[structopt(short="z", long="zoom", help="Pixels per unit on the plane", default_value = "350")]
+    zoom: f64,
+
+    #[structopt(long="centre-re", help="Real part of the point at the centre of the image", default_value = "0.0")]
+    centre_re: f64,
+
+    #[structopt(long="centre-im", help="Imaginary part of the point at the centre of the image", default_value = "0.0")]
+    centre_im: f64,
+
+    #[structopt(long="rotate", help="Anticlockwise rotation of the view in degrees", default_value = "0.0")]
+    rotate: f64,
+
+    #[structopt(long="axes", help="Axes running horizontally and vertically: re-im or im-re", default_value = "re-im")]
+    axes: Axes,
 
     #[structopt(short="d", long="delta", help="Steps between each coordinate", default_value = "0.05")]
     delta: f64,
 
+    #[structopt(long="sampler", help="Sample distribution: grid, random, jittered, halton or sobol", default_value = "grid")]
+    sampler: SamplerKind,
+
+    #[structopt(long="samples", help="Number of samples, instead of the number of grid points given by delta")]
+    samples: Option<usize>,
+
+    #[structopt(long="seed", help="Seed for the random samplers", default_value = "0")]
+    seed: usize,
+
+    #[structopt(long="shard", help="Take only this part of the samples and chains, as index/count, so that renders of every part with the same seed merge into the whole", default_value = "1/1")]
+    shard: Shard,
+
+    #[structopt(long="metropolis", help="Number of Metropolis-Hastings mutations to run alongside the sampler; needs a float accumulator")]
+    metropolis: Option<usize>,
+
+    #[structopt(long="chains", help="Number of independent Metropolis-Hastings chains", default_value = "64")]
+    chains: usize,
+
     #[structopt(short="l", long="loops", help="Number of iterations for each coordinate", default_value = "200")]
     loop_limit: u32,
 
-    #[structopt(long="re", help="Real offset", default_value = "0.0")]
+    #[structopt(long="bailout", help="Radius beyond which an orbit has escaped", default_value = "2.0")]
+    bailout: f64,
+
+    #[structopt(long="mode", help="Orbits to plot: buddha (escaping), anti (bounded) or all", default_value = "all")]
+    mode: OrbitMode,
+
+    #[structopt(long="skip", help="Iterations at the start of each orbit that are never plotted", default_value = "1")]
+    skip: usize,
+
+    #[structopt(long="min-iter", help="First plotted point of each orbit, counted after the skipped iterations", default_value = "0")]
+    min_iter: usize,
+
+    #[structopt(long="max-iter", help="Plotted point of each orbit to stop before, counted after the skipped iterations")]
+    max_iter: Option<usize>,
+
+    #[structopt(long="min-length", help="Drop escaping orbits with fewer plotted points than this", default_value = "0")]
+    min_length: usize,
+
+    #[structopt(long="accumulator", help="Per-pixel counter type: u16, u32, u64, f32 or f64", default_value = "u32")]
+    accumulator: AccumulatorKind,
+
+    #[structopt(long="splat", help="Spread points over nearby pixels: none, bilinear or gaussian[:sigma]; needs a float accumulator", default_value = "none")]
+    splat: Splat,
+
+    #[structopt(long="channels", help="Comma-separated loop limits for red, green and blue, e.g. 5000,500,50")]
+    channels: Option<List<u32>>,
+
+    #[structopt(long="colour-by", help="Colour points by iteration, escape, angle or speed into red, green and blue; needs a float accumulator")]
+    colour_by: Option<ColourBy>,
+
+    #[structopt(long="channel-factors", help="Comma-separated brightness exponents for each channel")]
+    channel_factors: Option<List<f64>>,
+
+    #[structopt(long="re", help="Real part of z0, from the formula's critical point, when it isn't sampled", default_value = "0.0")]
     off_real: f64,
 
-    #[structopt(long="im", help="Imaginary offset", default_value = "0.0")]
+    #[structopt(long="im", help="Imaginary part of z0, from the formula's critical point, when it isn't sampled", default_value = "0.0")]
     off_imaginary: f64,
-}
 
-#[derive(Debug, Copy, Clone)]
-struct State {
-    counter: usize,
-    max: usize,
-    just_finished: bool,
-    render_count: u8,
-}
+    #[structopt(long="c-re", help="Real part of c when it isn't sampled", default_value = "0.0")]
+    c_re: f64,
 
-fn get_rgba(hue: f64, sat: f64, val: f64) -> Rgba<u8> {
-    let hi = (hue/60.0).floor() % 6.0;
-    let f = (hue/60.0) - (hue/60.0).floor();
-    let p = val * (1.0 - sat);
-    let q = val * (1.0 - f * sat);
-    let t = val * (1.0 - (1.0-f) * sat);
-
-    match hi as u8 {
-        0 => Rgba([(255.0 * val) as u8, (255.0 * t) as u8,   (255.0 * p) as u8, 255]),
-        1 => Rgba([(255.0 * q) as u8,   (255.0 * val) as u8, (255.0 * p) as u8, 255]),
-        2 => Rgba([(255.0 * p) as u8,   (255.0 * val) as u8, (255.0 * t) as u8, 255]),
-        3 => Rgba([(255.0 * p) as u8,   (255.0 * q) as u8,   (255.0 * val) as u8, 255]),
-        4 => Rgba([(255.0 * t) as u8,   (255.0 * p) as u8,   (255.0 * val) as u8, 255]),
-        5 => Rgba([(255.0 * val) as u8, (255.0 * p) as u8,   (255.0 * q) as u8, 255]),
-        _ => Rgba([0,0,0,255])
-    }
-}
+    #[structopt(long="c-im", help="Imaginary part of c when it isn't sampled", default_value = "0.0")]
+    c_im: f64,
 
+    #[structopt(long="slice", help="The two sampled axes of (z0, c) space, from z0re, z0im, cre and cim", default_value = "cre,cim")]
+    slice: List<Axis>,
 
-fn mandelbrot(z: Complex64, x: f64, y: f64, cfg: &Config) -> Complex64 {
-    let c = Complex64::new(x, y);
-    z.powf(cfg.power) + c
-}
+    #[structopt(long="slice-angle", help="Rotation in degrees of the sampled plane towards the fixed axes", default_value = "0.0")]
+    slice_angle: f64,
 
-fn draw_point(image: &mut Pic<u16>, z: Complex64, cfg: &Config) {
-    let pos_x = (cfg.width as f64/2.0) + z.re * cfg.zoom as f64 - 0.5;
-    let pos_y = (cfg.height as f64/2.0) - z.im * cfg.zoom as f64 + 0.5;
+    #[structopt(long="output", help="Image screenshots are saved to on right-click, as .png, .tiff, .ppm or .bmp", default_value = "out.png")]
+    output: String,
 
-    if pos_x + 1.0 < 0.0 || pos_y < 0.0 {
-        return;
-    }
+    #[structopt(long="bit-depth", help="Bits per sample of screenshots, 8 or 16; 16-bit screenshots are saved without the text overlay", default_value = "8")]
+    bit_depth: u8,
 
-    let pos_x = pos_x as u32;
-    let pos_y = pos_y as u32;
+    #[structopt(long="no-overlay", help="Don't draw the status and configuration over the view")]
+    no_overlay: bool,
 
-    if pos_x >= cfg.width || pos_y >= cfg.height {
-        return;
-    }
+    #[structopt(long="checkpoint", help="Save the render in progress to this .rpx file, and when the window is closed, so it can be resumed with --resume")]
+    checkpoint: Option<String>,
 
-    let pixel = image.get_pixel_mut(pos_x as u32, pos_y as u32);
-    pixel[0] = pixel[0].saturating_add(1);
-}
+    #[structopt(long="checkpoint-every", help="Seconds between checkpoints", default_value = "600")]
+    checkpoint_every: usize,
 
-fn u16_to_u8(from: &Pic<u16>, to: &mut image::ImageBuffer<Rgba<u8>, Vec<u8>>, factor: f64) {
-    for ((_,_,p), (_,_,p2)) in from.enumerate_pixels().zip(to.enumerate_pixels_mut()) {
-        let p = p[0] as f64;
-        let val = 1.0 - ((-p)/factor).exp();
-        let c = (val * 255.0) as u8;
+    #[structopt(long="resume", help="Continue the render saved in the --checkpoint file, which must have the same settings")]
+    resume: bool,
 
-        *p2 = Rgba([c,c,c,255]);
-    }
+    #[structopt(long="screenshots", help="Screenshot naming: numbered (out_0001.png), timestamped or overwrite", default_value = "numbered")]
+    screenshots: Naming,
 }
 
-fn iterate_coordinate(x: f64, y: f64, p_data: SharedData, cfg: &Config) {
-    let mut z = Complex64::new(cfg.off_real, cfg.off_imaginary);
-    // Skip the first value to get rid of the square.
-    z = mandelbrot(z, x, y, &cfg);
-    let mut points = vec![];
+impl Config {
+    fn options(&self) -> Options {
+        Options {
+            width: self.width,
+            height: self.height,
+            zoom: self.zoom,
+            centre_re: self.centre_re,
+            centre_im: self.centre_im,
+            rotate: self.rotate,
+            axes: self.axes,
+            bounds: self.bounds,
+            re_min: self.re_min,
+            re_max: self.re_max,
+            im_min: self.im_min,
+            im_max: self.im_max,
+            region: self.region.clone(),
+            slice: self.slice.0.clone(),
+            off_real: self.off_real,
+            off_imaginary: self.off_imaginary,
+            c_re: self.c_re,
+            c_im: self.c_im,
+            slice_angle: self.slice_angle,
+            sampler: self.sampler,
+            delta: self.delta,
+            samples: self.samples,
+            seed: self.seed as u64,
+            shard: self.shard,
+            metropolis: self.metropolis,
+            chains: self.chains,
+            loop_limit: self.loop_limit,
+            channels: self.channels.as_ref().map(|l| l.0.clone()),
+            colour_by: self.colour_by,
+            mode: self.mode,
+            min_iter: self.min_iter,
+            max_iter: self.max_iter,
+            min_length: self.min_length,
+            accumulator: self.accumulator,
+            splat: self.splat,
+            factor: self.factor,
+            channel_factors: self.channel_factors.as_ref().map(|l| l.0.clone()),
+            auto_exposure: self.auto_exposure,
+            palette: self.palette.clone(),
+            output: self.output.clone(),
+            bit_depth: self.bit_depth,
+            checkpoint: self.checkpoint.clone(),
+            resume: self.resume,
+        }
+    }
 
-    for _ in 0..cfg.loop_limit {
-        z = mandelbrot(z, x, y, &cfg);
-        points.push(z);
+    /// Scales every loop limit, keeping them at least 1.
+    fn scale_loop_limits(&mut self, f: f64) {
+        let scale = |limit: u32| ((limit as f64 * f) as u32).max(1);
+
+        self.loop_limit = scale(self.loop_limit);
+        if let Some(ref mut limits) = self.channels {
+            for limit in limits.0.iter_mut() {
+                *limit = scale(*limit);
+            }
+        }
     }
-    
-    let mut pic = p_data.lock().expect("Lock failed");
-    let &mut (ref mut canvas, ref mut state) = pic.deref_mut();
 
-    for z in points {
-        draw_point(canvas, z, &cfg);
+    /// The progress counted by a complete render.
+    fn total(&self) -> usize {
+        let opts = self.options();
+        opts.sampler(&opts.region()).len() + opts.metropolis_steps() * self.chains
     }
+}
 
-    state.counter+=1;
+/// Shared between the render workers and the viewer.
+struct State<T> {
+    canvas: Mutex<Canvas<T>>,
+    counter: AtomicUsize,
+    position: Position,
+    max: AtomicUsize,
+    /// Stops the current render, when the viewer restarts it or closes.
+    cancel: AtomicBool,
+    /// The settings to restart rendering with.
+    restart: Mutex<Option<Config>>,
+    closed: AtomicBool,
 }
 
-fn output_buckets(canvas: &Pic<u16>) {
-    let step = 5000;
-    let mut buckets = vec![0;14];
+impl<T> State<T> {
+    /// Cancels the current render, and queues `cfg` to be rendered instead.
+    fn restart(&self, cfg: &Config) {
+        let mut restart = self.restart.lock().expect("Lock failed");
+        *restart = Some(Config { resume: false, ..cfg.clone() });
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+
+    /// Takes the queued settings, if any, letting the next render run.
+    fn take_restart(&self) -> Option<Config> {
+        let mut restart = self.restart.lock().expect("Lock failed");
+        let cfg = restart.take();
 
-    for (_, _, p) in canvas.enumerate_pixels() {
-        if p[0] == 0 {
-            continue;
+        // Cleared while locked, so that a restart queued after this one cancels it again.
+        if cfg.is_some() {
+            self.cancel.store(false, Ordering::SeqCst);
         }
-        
-        buckets[(p[0] / step) as usize] += 1;
+
+        cfg
     }
+}
 
-    println!("{:#?}", buckets);
+/// Renders the escape-time image of the view at a quarter of the size, for finding regions to render.
+fn escape_inset(cfg: &Config) -> image::ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let opts = cfg.options();
+    let formula = cfg.formula.build(cfg.power);
+    let escape = EscapeTime {
+        formula: &*formula,
+        slice: opts.slice(),
+        power: cfg.power,
+        loop_limit: *opts.channel_limits().iter().max().unwrap(),
+        bailout: cfg.bailout,
+    };
+
+    let full = opts.view();
+    let view = Viewport {
+        width: (full.width / 4).max(1),
+        height: (full.height / 4).max(1),
+        zoom: full.zoom / 4.0,
+        ..full
+    };
+
+    let mut inset = image::ImageBuffer::new(view.width, view.height);
+    escape.draw(&view, cfg.escape_time, cfg.palette.as_ref(), &mut inset);
+    inset
 }
 
-fn display(cfg: &Config, shared_pic: SharedData) {
+/// Shows the render as it progresses, until the window is closed.
+///
+/// Dragging with the left button pans and the wheel zooms around the cursor. The arrow keys change
+/// the power and loop limit, and Page Up and Page Down the grid spacing. Each of these restarts the
+/// render. Plus and minus double and halve the exposure, which scales densities before any tone
+/// curve, T cycles the tone curve, E shows the escape-time image and right-clicking saves a
+/// screenshot.
+fn display<T: Accumulator>(cfg: &Config, state: &State<T>) {
     let mut window: PistonWindow = WindowSettings::new("Pixi", (cfg.width, cfg.height))
         .exit_on_esc(true)
         .opengl(OpenGL::V4_5)
@@ -170,40 +341,69 @@ fn display(cfg: &Config, shared_pic: SharedData) {
     let mut buffer = image::ImageBuffer::new(cfg.width, cfg.height);
     let mut texture = Texture::from_image( &mut window.factory, &buffer, &TextureSettings::new()).expect("Error creating texture.");
 
-    let font = Vec::from(include_bytes!("SourceSansPro-Light.ttf") as &[u8]);
-    let font = FontCollection::from_bytes(font).into_font().unwrap();
-    let scale = Scale { x: 12.4 * 2.0, y: 12.4 };
+    let overlay = Overlay::new();
 
-    let cfg_string = format!("{:#?}", cfg);
-    let cfg_string: Vec<_> = cfg_string.lines().enumerate().collect();
+    let mut cfg = cfg.clone();
+    let mut cfg_string = format!("{:#?}", cfg);
 
-    let mut factor = cfg.factor;
-    let now = std::time::Instant::now();
+    let mut exposure = 1.0;
+    let mut inset: Option<image::ImageBuffer<Rgba<u8>, Vec<u8>>> = None;
+    let mut show_inset = false;
+    let mut cursor = [0.0, 0.0];
+    let mut drag_from: Option<[f64; 2]> = None;
+    let mut now = std::time::Instant::now();
     let mut force_rerender = false;
+    let mut just_finished = false;
+    let mut render_count = 0;
 
     while let Some(e) = window.next() {
+        let mut changed = false;
+
         if let Some(_) = e.render_args() {
-            let mut pic_data = shared_pic.lock().expect("Lock failed");
-            let &mut (ref canvas, ref mut state) = pic_data.deref_mut();
+            let counter = state.counter.load(Ordering::SeqCst);
+            let max = state.max.load(Ordering::SeqCst);
+
+            if force_rerender || (render_count == 30 && (!just_finished || counter != max)) {
+                let (overflowed, factors) = {
+                    let canvas = state.canvas.lock().expect("Lock failed");
+                    let factors = cfg.options().exposed_factors(&canvas);
+                    tonemap::draw(&canvas, cfg.curve, &factors, cfg.auto_exposure, exposure, cfg.palette.as_ref(), &mut buffer);
+                    (canvas.overflowed(), factors)
+                };
+
+                let mut status = format!("{:.*}% - {}s - {}", 2, (counter as f64)/(max as f64)*100.0, now.elapsed().as_secs(), cfg.curve);
+                if exposure != 1.0 {
+                    status += &format!(" - exposure x{}", exposure);
+                }
+                if overflowed > 0 {
+                    status += &format!(" - {} overflowed", overflowed);
+                }
 
-            if force_rerender || (state.render_count == 30 && (!state.just_finished || state.counter != state.max)) {
-                u16_to_u8(&canvas, &mut buffer, factor);
-                draw_text_mut(&mut buffer, Rgba([255, 255, 255, 255]), 10, 10, scale, &font, &format!("{:.*}% - {}s", 2, (state.counter as f64)/(state.max as f64)*100.0, now.elapsed().as_secs()));
+                if !cfg.no_overlay {
+                    overlay.draw(&mut buffer, &status, &cfg_string);
+                }
 
-                for &(i, l) in cfg_string.iter() {
-                    draw_text_mut(&mut buffer, Rgba([255, 255, 255, 255]), 10, 20 + i as u32*13, scale, &font, l);
+                match inset {
+                    Some(ref inset) if show_inset => {
+                        let left = cfg.width - inset.width();
+                        for (x, y, p) in inset.enumerate_pixels() {
+                            buffer.put_pixel(left + x, y, *p);
+                        }
+                    },
+                    _ => (),
                 }
 
-                state.just_finished = state.counter == state.max;
-                state.render_count = 0;
+                just_finished = counter == max;
+                render_count = 0;
                 force_rerender = false;
 
-                if state.just_finished {
-                    //output_buckets(&canvas);
+                // The factors that give the same image without the exposure.
+                if just_finished && cfg.curve == Curve::Exp {
+                    println!("Factors: {:?}", factors.iter().map(|f| f / exposure).collect::<Vec<_>>());
                 }
             }
 
-            state.render_count += 1;
+            render_count += 1;
 
             texture.update(&mut window.encoder, &buffer).expect("Error flipping buffer");
             window.draw_2d(&e, |c,g| {
@@ -211,46 +411,302 @@ fn display(cfg: &Config, shared_pic: SharedData) {
             });
         }
 
+        if let Some(pos) = e.mouse_cursor_args() {
+            cursor = pos;
+        }
+
+        if let Some(Button::Mouse(MouseButton::Left)) = e.press_args() {
+            drag_from = Some(cursor);
+        }
+
         if let Some(btn) = e.release_args() {
             match btn {
-                Button::Mouse(MouseButton::Right) => buffer.save("out.png").unwrap(),
+                Button::Mouse(MouseButton::Left) => {
+                    if let Some(from) = drag_from.take() {
+                        if (from[0] - cursor[0]).abs() + (from[1] - cursor[1]).abs() >= 2.0 {
+                            let view = cfg.options().view();
+                            let moved = view.unproject(from[0] as u32, from[1] as u32) - view.unproject(cursor[0] as u32, cursor[1] as u32);
+                            cfg.centre_re += moved.re;
+                            cfg.centre_im += moved.im;
+                            changed = true;
+                        }
+                    }
+                },
+                Button::Mouse(MouseButton::Right) => {
+                    let path = output::screenshot_path(&cfg.output, cfg.screenshots);
+                    let res = if cfg.bit_depth == 16 {
+                        let canvas = state.canvas.lock().expect("Lock failed");
+                        let factors = cfg.options().exposed_factors(&canvas);
+                        let rgb = tonemap::map(&canvas, cfg.curve, &factors, cfg.auto_exposure, exposure, cfg.palette.as_ref());
+                        output::save(&path, cfg.width, cfg.height, Samples::U16(&output::to_u16(&rgb)))
+                    } else {
+                        output::save(&path, cfg.width, cfg.height, Samples::U8(&output::rgb_bytes(&buffer)))
+                    };
+
+                    match res {
+                        Ok(()) => println!("Saved {}", path),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                },
+                Button::Keyboard(Key::T) => {
+                    cfg.curve = cfg.curve.next();
+                    cfg_string = format!("{:#?}", cfg);
+                    force_rerender = true;
+                },
+                Button::Keyboard(Key::E) => {
+                    if inset.is_none() {
+                        inset = Some(escape_inset(&cfg));
+                    }
+                    show_inset = !show_inset;
+                    force_rerender = true;
+                },
+                Button::Keyboard(Key::Equals) | Button::Keyboard(Key::NumPadPlus) => {
+                    exposure *= 2.0;
+                    force_rerender = true;
+                },
+                Button::Keyboard(Key::Minus) | Button::Keyboard(Key::NumPadMinus) => {
+                    exposure /= 2.0;
+                    force_rerender = true;
+                },
+                Button::Keyboard(Key::Right) => {
+                    cfg.power += 1.0;
+                    changed = true;
+                },
+                // Powers of 2 or below are left alone, rather than raised to the lowest the key steps down to.
+                Button::Keyboard(Key::Left) if cfg.power > 2.0 => {
+                    cfg.power = (cfg.power - 1.0).max(2.0);
+                    changed = true;
+                },
+                Button::Keyboard(Key::Up) => {
+                    cfg.scale_loop_limits(2.0);
+                    changed = true;
+                },
+                Button::Keyboard(Key::Down) => {
+                    cfg.scale_loop_limits(0.5);
+                    changed = true;
+                },
+                Button::Keyboard(Key::PageUp) => {
+                    cfg.delta /= 2.0;
+                    changed = true;
+                },
+                Button::Keyboard(Key::PageDown) => {
+                    cfg.delta *= 2.0;
+                    changed = true;
+                },
                 _ => (),
             }
         }
 
         if let Some(scroll) = e.mouse_scroll_args() {
-            if scroll[1] == -1.0 {
-                factor *= 2.0;
-                force_rerender = true;
-            } else if scroll[1] == 1.0 {
-                factor /= 2.0;
-                force_rerender = true;
+            if scroll[1] != 0.0 {
+                // Keep the point under the cursor where it is.
+                let f = if scroll[1] > 0.0 { 2.0 } else { 0.5 };
+                let view = cfg.options().view();
+                let at = view.unproject(cursor[0] as u32, cursor[1] as u32);
+                let centre = at + (view.centre - at) / f;
+
+                cfg.zoom *= f;
+                cfg.centre_re = centre.re;
+                cfg.centre_im = centre.im;
+                changed = true;
+            }
+        }
+
+        if changed {
+            state.restart(&cfg);
+            cfg.resume = false;
+            cfg_string = format!("{:#?}", cfg);
+
+            inset = if show_inset { Some(escape_inset(&cfg)) } else { None };
+            now = std::time::Instant::now();
+            just_finished = false;
+            force_rerender = true;
+        }
+    }
+
+    state.closed.store(true, Ordering::SeqCst);
+    state.cancel.store(true, Ordering::SeqCst);
+}
+
+/// Renders `cfg` into the shared state, until it's finished or cancelled.
+fn render<T: Accumulator>(cfg: &Config, state: &State<T>) {
+    let opts = cfg.options();
+    let limits = opts.channel_limits();
+
+    let formula = cfg.formula.build(cfg.power);
+    let tracer = Tracer {
+        formula: &*formula,
+        slice: opts.slice(),
+        loop_limit: *limits.iter().max().unwrap(),
+        bailout: cfg.bailout,
+        skip: cfg.skip,
+    };
+    let view = opts.view();
+
+    let region = opts.region();
+    let sampler = opts.sampler(&region);
+
+    let metropolis = Metropolis {
+        tracer: &tracer,
+        view,
+        region: &region,
+        limits: &limits,
+        filter: opts.filter(),
+        seed: cfg.seed as u64,
+        shard: cfg.shard,
+    };
+
+    let renderer = Renderer {
+        tracer: &tracer,
+        view,
+        limits: &limits,
+        filter: opts.filter(),
+        splat: cfg.splat,
+        colouring: cfg.colour_by.map(|by| Colouring {
+            by,
+            hue: cfg.colour_factor,
+            limit: limits[0],
+        }),
+        cancel: &state.cancel,
+    };
+
+    let steps = opts.metropolis_steps();
+    let (canvas, position) = match (cfg.resume, cfg.checkpoint.as_ref()) {
+        (true, Some(path)) => raw::resume::<T>(path, &format!("{:#?}", cfg)).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        _ => (Canvas::new(cfg.width, cfg.height, renderer.channels()), Position::default()),
+    };
+
+    {
+        let mut shared = state.canvas.lock().expect("Lock failed");
+        *shared = canvas;
+        state.position.samples.store(position.samples.load(Ordering::SeqCst), Ordering::SeqCst);
+        state.position.segments.store(position.segments.load(Ordering::SeqCst), Ordering::SeqCst);
+        state.counter.store(position.done(cfg.chains, steps), Ordering::SeqCst);
+        state.max.store(sampler.len() + steps * cfg.chains, Ordering::SeqCst);
+    }
+
+    let mut last_checkpoint = std::time::Instant::now();
+    let finished = AtomicBool::new(false);
+
+    rayon::join(
+    || {
+        rayon::join(
+            || renderer.render_samples(&sampler, &state.canvas, &state.counter, &state.position),
+            || renderer.render_metropolis(&metropolis, cfg.chains, steps, &state.canvas, &state.counter, &state.position));
+        finished.store(true, Ordering::SeqCst);
+    },
+    || if let Some(ref path) = cfg.checkpoint {
+        loop {
+            if finished.load(Ordering::SeqCst) || state.cancel.load(Ordering::SeqCst) {
+                break;
             }
+
+            if last_checkpoint.elapsed().as_secs() >= cfg.checkpoint_every as u64 {
+                match raw::save_checkpoint(path, &state.canvas, &state.position, &format!("{:#?}", cfg)) {
+                    Ok(()) => println!("Checkpoint saved to {}", path),
+                    Err(e) => eprintln!("{}", e),
+                }
+                last_checkpoint = std::time::Instant::now();
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+    });
+}
+
+/// Renders with each set of settings the viewer asks for until it's closed, returning the settings
+/// the shared canvas was last rendered with.
+fn render_all<T: Accumulator>(cfg: &Config, state: &State<T>) -> Config {
+    let mut cfg = cfg.clone();
+
+    loop {
+        render(&cfg, state);
+
+        // Wait for the viewer to restart the render, or close.
+        loop {
+            if state.closed.load(Ordering::SeqCst) {
+                return cfg;
+            }
+
+            if let Some(next) = state.take_restart() {
+                cfg = next;
+                break;
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(100));
         }
     }
+}
 
-    // Horrid hack to make both threads exit.
-    panic!("Exiting.");
+fn run<T: Accumulator + 'static>(cfg: &Config) {
+    let state = Arc::new(State {
+        canvas: Mutex::new(Canvas::<T>::new(cfg.width, cfg.height, cfg.options().channel_count())),
+        counter: AtomicUsize::new(0),
+        position: Position::default(),
+        max: AtomicUsize::new(cfg.total()),
+        cancel: AtomicBool::new(false),
+        restart: Mutex::new(None),
+        closed: AtomicBool::new(false),
+    });
+
+    // The window's event loop runs on the main thread, and rendering on its own thread, which
+    // hands the work to the rayon pool.
+    let rendering = {
+        let (cfg, state) = (cfg.clone(), state.clone());
+        std::thread::spawn(move || render_all(&cfg, &state))
+    };
+
+    display(cfg, &state);
+    let cfg = rendering.join().expect("Rendering failed");
+
+    // Once the window is closed, keep an unfinished render so it can be resumed.
+    if let Some(ref path) = cfg.checkpoint {
+        if state.counter.load(Ordering::SeqCst) != state.max.load(Ordering::SeqCst) {
+            match raw::save_checkpoint(path, &state.canvas, &state.position, &format!("{:#?}", cfg)) {
+                Ok(()) => println!("Checkpoint saved to {}", path),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
 }
 
 fn main() {
     let cfg = Config::from_args();
 
-    let mut canvas = image::ImageBuffer::from_pixel(cfg.width, cfg.height, LumaA([0,u16::max_value()]));
+    if let Err(e) = cfg.options().check() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    match cfg.accumulator {
+        AccumulatorKind::U16 => run::<u16>(&cfg),
+        AccumulatorKind::U32 => run::<u32>(&cfg),
+        AccumulatorKind::U64 => run::<u64>(&cfg),
+        AccumulatorKind::F32 => run::<f32>(&cfg),
+        AccumulatorKind::F64 => run::<f64>(&cfg),
+    }
+}
 
-    let coords: Vec<_> = (0_u32..).map(|x| -cfg.bounds + x as f64 * cfg.delta).take_while(|&x| x < cfg.bounds).collect();
-    let all_coords: Vec<_> = coords.iter().cartesian_product(coords.iter()).collect();
+#[cfg(test)]
+mod tests {
+    use super::Config;
+    use structopt::StructOpt;
 
-    let mut state = State {
-        counter: 0,
-        max: all_coords.len(),
-        just_finished: false,
-        render_count: 0,
-    };
+    fn parse(args: &[&str]) -> Config {
+        Config::from_clap(Config::clap().get_matches_from(args))
+    }
 
-    let shared_pic = Arc::new(Mutex::new((&mut canvas, &mut state)));
+    #[test]
+    fn checkpoint_interval_takes_a_value() {
+        assert_eq!(parse(&["rpixi"]).checkpoint_every, 600);
+        assert_eq!(parse(&["rpixi", "--checkpoint-every", "30"]).checkpoint_every, 30);
+    }
 
-    rayon::join(
-    || all_coords.par_iter().for_each(|&(&y, &x)| iterate_coordinate(x, y, shared_pic.clone(), &cfg) ),
-    || display(&cfg, shared_pic.clone()));
+    #[test]
+    fn seed_takes_a_value() {
+        assert_eq!(parse(&["rpixi", "--seed", "42"]).seed, 42);
+    }
 }
@@ -0,0 +1,87 @@
+extern crate rpixi;
+use rpixi::canvas::{Accumulator, AccumulatorKind};
+use rpixi::raw::{self, Header};
+
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+use structopt::StructOpt;
+
+use std::fs;
+use std::path::PathBuf;
+
+/// Sums raw canvases of the same image, rendered with different seeds or as different shards.
+#[derive(Debug, StructOpt)]
+struct Config {
+    #[structopt(help="Raw canvas files to merge")]
+    inputs: Vec<String>,
+
+    #[structopt(long="output", help="Raw canvas file to save the sum to", default_value = "merged.rpx")]
+    output: String,
+}
+
+fn run<T: Accumulator>(cfg: &Config, headers: &[Header]) -> Result<(), String> {
+    let (_, mut canvas) = raw::open::<T>(&cfg.inputs[0])?;
+
+    for path in &cfg.inputs[1..] {
+        let (_, other) = raw::open::<T>(path)?;
+        canvas.merge(&other);
+        println!("Merged {}", path);
+    }
+
+    raw::save(&cfg.output, &canvas, &Header::merged_config(headers))?;
+
+    if canvas.overflowed() > 0 {
+        eprintln!("Warning: {} additions overflowed the accumulator, try a wider --accumulator.", canvas.overflowed());
+    }
+
+    println!("Saved {}", cfg.output);
+    Ok(())
+}
+
+/// Checks every file can be merged with the first, and took different samples from every other,
+/// before reading any canvases.
+fn merge(cfg: &Config) -> Result<(), String> {
+    if cfg.inputs.len() < 2 {
+        return Err("Expected at least two raw canvas files to merge.".to_string());
+    }
+
+    let mut paths: Vec<PathBuf> = vec![];
+    for path in &cfg.inputs {
+        let canonical = fs::canonicalize(path).map_err(|e| format!("Unable to open {}: {}", path, e))?;
+        if paths.contains(&canonical) {
+            return Err(format!("{} is given more than once.", path));
+        }
+        paths.push(canonical);
+    }
+
+    let headers = cfg.inputs.iter().map(|path| raw::open_header(path)).collect::<Result<Vec<_>, _>>()?;
+    for (i, header) in headers.iter().enumerate().skip(1) {
+        headers[0].compatible(header)
+            .map_err(|e| format!("{} can't be merged with {}: {}", cfg.inputs[i], cfg.inputs[0], e))?;
+
+        for (j, earlier) in headers[..i].iter().enumerate() {
+            earlier.disjoint(header)
+                .map_err(|e| format!("{} can't be merged with {}: {}", cfg.inputs[i], cfg.inputs[j], e))?;
+        }
+    }
+
+    let first = &headers[0];
+
+    match first.kind {
+        AccumulatorKind::U16 => run::<u16>(cfg, &headers),
+        AccumulatorKind::U32 => run::<u32>(cfg, &headers),
+        AccumulatorKind::U64 => run::<u64>(cfg, &headers),
+        AccumulatorKind::F32 => run::<f32>(cfg, &headers),
+        AccumulatorKind::F64 => run::<f64>(cfg, &headers),
+    }
+}
+
+fn main() {
+    let cfg = Config::from_args();
+
+    if let Err(e) = merge(&cfg) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
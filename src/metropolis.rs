@@ -0,0 +1,148 @@
+use num::complex::Complex64;
+
+use orbit::{Filter, Tracer};
+use region::Region;
+use sampler::{Rng, Shard};
+use viewport::Viewport;
+
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Chance of proposing a fresh point from the region, instead of a small mutation.
+const FRESH_CHANCE: f64 = 0.25;
+
+/// Uniform samples traced to start each chain, which also estimate the mean contribution.
+const START_SAMPLES: usize = 10_000;
+
+/// Metropolis–Hastings sampler, which mutates c values whose orbits already land in the viewport.
+///
+/// The target density is the number of visible orbit points, so zoomed-in views spend their time
+/// on the few orbits that pass through them rather than discarding almost every sample.
+///
+/// Orbits are plotted with a weight of the mean contribution of uniform samples over their own,
+/// which undoes the bias towards bright orbits: each mutation then adds as much to the image as a
+/// uniform sample would on average.
+pub struct Metropolis<'a> {
+    pub tracer: &'a Tracer<'a>,
+    pub view: Viewport,
+    pub region: &'a Region,
+    pub limits: &'a [u32],
+    pub filter: Filter,
+    pub seed: u64,
+    /// Chains are numbered apart in each shard, so shards with the same seed run different chains.
+    pub shard: Shard,
+}
+
+impl<'a> Metropolis<'a> {
+    fn contribution(&self, points: &[Complex64], escaped: Option<usize>) -> usize {
+        self.limits.iter()
+            .filter_map(|&limit| self.filter.channel_points(points, escaped, limit))
+            .map(|points| self.view.visible(points))
+            .sum()
+    }
+
+    fn mutate(&self, c: Complex64, rng: &mut Rng) -> Complex64 {
+        if rng.next_f64() < FRESH_CHANCE {
+            return rng.point_in(&self.region.bounds);
+        }
+
+        // Log-uniform step between ten pixels and a hundredth of a pixel.
+        let pixel = 1.0 / self.view.zoom;
+        let radius = 10.0 * pixel * (1e-3_f64).powf(rng.next_f64());
+        let angle = 2.0 * PI * rng.next_f64();
+
+        c + Complex64::from_polar(&radius, &angle)
+    }
+
+    /// Starts a chain from one of a batch of uniform samples, picked in proportion to their
+    /// contributions, or `None` if none of them were visible.
+    ///
+    /// Also returns the mean contribution of the samples, counting those outside the region as zero.
+    /// Setting `cancel` stops the chain from starting.
+    pub fn start(&self, chain: usize, cancel: &AtomicBool) -> (Option<Chain>, f64) {
+        let stream = chain * self.shard.count + self.shard.index - 1;
+        let mut rng = Rng::new(self.seed, stream as u64);
+        let mut points = vec![];
+        let mut picked = vec![];
+        let mut start = None;
+        let mut total = 0;
+
+        for _ in 0..START_SAMPLES {
+            if cancel.load(Ordering::Relaxed) {
+                return (None, 0.0);
+            }
+
+            let c = rng.point_in(&self.region.bounds);
+            if !self.region.contains(c) {
+                continue;
+            }
+
+            let escaped = self.tracer.trace(c, &mut points);
+            let contribution = self.contribution(&points, escaped);
+            total += contribution;
+
+            // Replacing the pick with this chance keeps each sample with a chance proportional to its contribution.
+            if contribution > 0 && rng.next_f64() * (total as f64) < contribution as f64 {
+                start = Some((c, escaped, contribution));
+                ::std::mem::swap(&mut points, &mut picked);
+            }
+        }
+
+        let chain = start.map(|(c, escaped, contribution)| Chain {
+            rng,
+            c,
+            points: picked,
+            proposal: vec![],
+            escaped,
+            contribution,
+        });
+
+        (chain, total as f64 / START_SAMPLES as f64)
+    }
+
+    /// Advances a chain by `steps` mutations, calling `plot` with the current orbit and its weight
+    /// after each one. `mean` is the mean contribution of uniform samples, as estimated by `start`.
+    ///
+    /// Returns the number of orbit points traced, counting one for each mutation.
+    pub fn run<F>(&self, chain: &mut Chain, steps: usize, mean: f64, mut plot: F) -> usize
+        where F: FnMut(&[Complex64], Option<usize>, f64)
+    {
+        let mut traced = steps;
+
+        for _ in 0..steps {
+            let new_c = self.mutate(chain.c, &mut chain.rng);
+
+            // Proposals outside the region have no contribution, so are always rejected.
+            if !self.region.contains(new_c) {
+                plot(&chain.points, chain.escaped, mean / chain.contribution as f64);
+                continue;
+            }
+
+            let new_escaped = self.tracer.trace(new_c, &mut chain.proposal);
+            traced += chain.proposal.len();
+            let new_contribution = self.contribution(&chain.proposal, new_escaped);
+
+            // Both kinds of proposal are symmetric, so the acceptance is just the ratio of contributions.
+            if chain.rng.next_f64() * (chain.contribution as f64) < new_contribution as f64 {
+                chain.c = new_c;
+                chain.escaped = new_escaped;
+                chain.contribution = new_contribution;
+                ::std::mem::swap(&mut chain.points, &mut chain.proposal);
+            }
+
+            plot(&chain.points, chain.escaped, mean / chain.contribution as f64);
+        }
+
+        traced
+    }
+}
+
+/// The state of a single Metropolis–Hastings chain.
+pub struct Chain {
+    rng: Rng,
+    c: Complex64,
+    points: Vec<Complex64>,
+    proposal: Vec<Complex64>,
+    escaped: Option<usize>,
+    contribution: usize,
+}
@@ -0,0 +1,204 @@
+use num::complex::Complex64;
+
+use canvas::{Accumulator, AccumulatorKind, Canvas, Splat};
+use colouring::ColourBy;
+use orbit::{Filter, OrbitMode};
+use output;
+use palette::Palette;
+use region::{Region, Shape};
+use sampler::{Bounds, Sampler, SamplerKind, Shard};
+use slice::{Axis, Slice};
+use tonemap;
+use viewport::{Axes, Viewport};
+
+/// The render options shared by the viewer and the command line renderer, and the settings derived
+/// from them.
+///
+/// Each binary parses its own options, and converts them into these to check and use them.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub width: u32,
+    pub height: u32,
+    pub zoom: f64,
+    pub centre_re: f64,
+    pub centre_im: f64,
+    pub rotate: f64,
+    pub axes: Axes,
+
+    pub bounds: f64,
+    pub re_min: Option<f64>,
+    pub re_max: Option<f64>,
+    pub im_min: Option<f64>,
+    pub im_max: Option<f64>,
+    pub region: Shape,
+
+    pub slice: Vec<Axis>,
+    pub off_real: f64,
+    pub off_imaginary: f64,
+    pub c_re: f64,
+    pub c_im: f64,
+    pub slice_angle: f64,
+
+    pub sampler: SamplerKind,
+    pub delta: f64,
+    pub samples: Option<usize>,
+    pub seed: u64,
+    pub shard: Shard,
+    pub metropolis: Option<usize>,
+    pub chains: usize,
+
+    pub loop_limit: u32,
+    pub channels: Option<Vec<u32>>,
+    pub colour_by: Option<ColourBy>,
+    pub mode: OrbitMode,
+    pub min_iter: usize,
+    pub max_iter: Option<usize>,
+    pub min_length: usize,
+    pub accumulator: AccumulatorKind,
+    pub splat: Splat,
+
+    /// The brightness factor of channels without one in `channel_factors`.
+    pub factor: f64,
+    pub channel_factors: Option<Vec<f64>>,
+    pub auto_exposure: Option<f64>,
+    pub palette: Option<Palette>,
+
+    pub output: String,
+    pub bit_depth: u8,
+    pub checkpoint: Option<String>,
+    pub resume: bool,
+}
+
+impl Options {
+    pub fn view(&self) -> Viewport {
+        Viewport {
+            centre: Complex64::new(self.centre_re, self.centre_im),
+            rotation: self.rotate.to_radians(),
+            axes: self.axes,
+            ..Viewport::new(self.width, self.height, self.zoom)
+        }
+    }
+
+    pub fn slice(&self) -> Slice {
+        Slice {
+            sampled: (self.slice[0], self.slice[1]),
+            origin: [self.off_real, self.off_imaginary, self.c_re, self.c_im],
+            angle: self.slice_angle.to_radians(),
+        }
+    }
+
+    pub fn region(&self) -> Region {
+        let bounds = Bounds {
+            re_min: self.re_min.unwrap_or(-self.bounds),
+            re_max: self.re_max.unwrap_or(self.bounds),
+            im_min: self.im_min.unwrap_or(-self.bounds),
+            im_max: self.im_max.unwrap_or(self.bounds),
+        };
+
+        Region::new(self.region.clone(), bounds)
+    }
+
+    /// The sampler over `region`, which should be this render's region.
+    pub fn sampler<'a>(&self, region: &'a Region) -> Sampler<'a> {
+        Sampler::new(self.sampler, region, self.delta, self.samples, self.seed, self.shard)
+    }
+
+    pub fn channel_limits(&self) -> Vec<u32> {
+        match self.channels {
+            Some(ref limits) => limits.clone(),
+            None => vec![self.loop_limit],
+        }
+    }
+
+    /// The number of canvas channels: one per loop limit, or red, green and blue when colouring points.
+    pub fn channel_count(&self) -> usize {
+        match self.colour_by {
+            Some(_) => 3,
+            None => self.channel_limits().len(),
+        }
+    }
+
+    pub fn channel_factors(&self) -> Vec<f64> {
+        match self.channel_factors {
+            Some(ref factors) => factors.clone(),
+            None => vec![self.factor; self.channel_count()],
+        }
+    }
+
+    /// The channel factors, derived from the canvas when auto-exposure is on.
+    pub fn exposed_factors<T: Accumulator>(&self, canvas: &Canvas<T>) -> Vec<f64> {
+        tonemap::exposed_factors(canvas, &self.channel_factors(), self.auto_exposure)
+    }
+
+    pub fn filter(&self) -> Filter {
+        Filter {
+            mode: self.mode,
+            min_iter: self.min_iter,
+            max_iter: self.max_iter.unwrap_or(usize::MAX),
+            min_length: self.min_length,
+        }
+    }
+
+    /// The mutations run by each Metropolis–Hastings chain, sharing `--metropolis` between the chains
+    /// of every shard.
+    pub fn metropolis_steps(&self) -> usize {
+        match self.metropolis {
+            Some(n) => (n as f64 / (self.chains * self.shard.count) as f64).ceil() as usize,
+            None => 0,
+        }
+    }
+
+    /// Checks the options fit together, returning the problem if not.
+    pub fn check(&self) -> Result<(), String> {
+        let limits = self.channel_limits();
+
+        if limits.is_empty() || limits.len() > 3 || self.channel_factors().len() != self.channel_count() {
+            return Err("Expected between one and three channels, with one factor for each.".to_string());
+        }
+
+        if self.slice.len() != 2 || self.slice[0] == self.slice[1] {
+            return Err("Expected two different sampled axes.".to_string());
+        }
+
+        if self.delta <= 0.0 || self.delta.is_nan() {
+            return Err("The grid spacing --delta must be positive.".to_string());
+        }
+
+        if self.sampler == SamplerKind::Grid && self.samples.is_some() {
+            return Err("The grid sampler is spaced by --delta, so takes no --samples.".to_string());
+        }
+
+        let bounds = self.region().bounds;
+        if !(bounds.re_min < bounds.re_max && bounds.im_min < bounds.im_max) {
+            return Err("The minimum bounds must be below the maximum bounds.".to_string());
+        }
+
+        if self.colour_by.is_some() && (limits.len() != 1 || !self.accumulator.is_float()) {
+            return Err("Colouring points needs a single loop limit and a floating-point accumulator, e.g. --accumulator f32.".to_string());
+        }
+
+        if self.min_iter >= self.max_iter.unwrap_or(usize::MAX) {
+            return Err("The minimum plotted iteration must be below the maximum.".to_string());
+        }
+
+        if self.palette.is_some() && self.channel_count() != 1 {
+            return Err("Palettes only apply to a single channel.".to_string());
+        }
+
+        tonemap::check_auto_exposure(self.auto_exposure)?;
+
+        if self.splat != Splat::None && !self.accumulator.is_float() {
+            return Err("Splatting needs a floating-point accumulator, e.g. --accumulator f32.".to_string());
+        }
+
+        if self.metropolis.is_some() && !self.accumulator.is_float() {
+            return Err("Metropolis-Hastings sampling weights orbits, so needs a floating-point accumulator, e.g. --accumulator f32.".to_string());
+        }
+
+        if self.resume && self.checkpoint.is_none() {
+            return Err("Resuming needs the --checkpoint file to resume from.".to_string());
+        }
+
+        output::check_bit_depth(&self.output, self.bit_depth)
+    }
+}
@@ -0,0 +1,187 @@
+use num::complex::Complex64;
+
+use args::ParseError;
+use formula::{Formula, Orbit};
+use slice::Slice;
+
+use std::ops::Range;
+use std::str::FromStr;
+
+/// Which orbits get plotted, depending on whether they escaped.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OrbitMode {
+    /// Only orbits that escape within the loop limit.
+    Buddha,
+    /// Only orbits that never escape.
+    Anti,
+    /// Every orbit.
+    All,
+}
+
+impl OrbitMode {
+    pub fn keep(&self, escaped: bool) -> bool {
+        match *self {
+            OrbitMode::Buddha => escaped,
+            OrbitMode::Anti => !escaped,
+            OrbitMode::All => true,
+        }
+    }
+}
+
+impl FromStr for OrbitMode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<OrbitMode, ParseError> {
+        match s.trim().to_lowercase().as_str() {
+            "buddha" => Ok(OrbitMode::Buddha),
+            "anti" => Ok(OrbitMode::Anti),
+            "all" => Ok(OrbitMode::All),
+            _ => Err(format!("Unknown mode: {}", s).into()),
+        }
+    }
+}
+
+pub struct Tracer<'a> {
+    pub formula: &'a dyn Formula,
+    /// Maps sample points to the z0 and c the orbit starts from.
+    pub slice: Slice,
+    pub loop_limit: u32,
+    pub bailout: f64,
+    /// Iterations traced but not recorded at the start of each orbit. Skipping the first gets rid
+    /// of the square of sample points.
+    pub skip: usize,
+}
+
+impl<'a> Tracer<'a> {
+    /// Traces the orbit of the sample point `s` into `points`, stopping early if it escapes.
+    ///
+    /// Returns the number of points produced before escaping, or `None` if the orbit stayed bounded.
+    pub fn trace(&self, s: Complex64, points: &mut Vec<Complex64>) -> Option<usize> {
+        points.clear();
+        let (z0, c) = self.slice.point(s);
+        let z0 = z0 + self.formula.critical_point();
+        let mut prev = z0;
+
+        for (i, z) in Orbit::new(self.formula, z0, c).take(self.loop_limit as usize + self.skip).enumerate() {
+            if i >= self.skip {
+                points.push(z);
+            }
+
+            if self.formula.escaped(z, prev, self.bailout) {
+                return Some(points.len());
+            }
+
+            prev = z;
+        }
+
+        None
+    }
+}
+
+/// Which orbits, and which part of each orbit, get plotted.
+#[derive(Debug, Copy, Clone)]
+pub struct Filter {
+    pub mode: OrbitMode,
+    /// The first recorded point plotted.
+    pub min_iter: usize,
+    /// The recorded point plotting stops before.
+    pub max_iter: usize,
+    /// Escaping orbits shorter than this are dropped.
+    pub min_length: usize,
+}
+
+impl Filter {
+    /// Plots every point of every orbit kept by `mode`.
+    pub fn new(mode: OrbitMode) -> Filter {
+        Filter {
+            mode,
+            min_iter: 0,
+            max_iter: usize::MAX,
+            min_length: 0,
+        }
+    }
+
+    /// The range of a traced orbit that is plotted into a channel with the given loop limit, if any.
+    ///
+    /// An orbit only counts as escaped for a channel if it escaped within that channel's limit.
+    pub fn range(&self, len: usize, escaped: Option<usize>, limit: u32) -> Option<Range<usize>> {
+        let limit = limit as usize;
+        let escaped = match escaped {
+            Some(n) => n <= limit,
+            None => false,
+        };
+
+        let len = len.min(limit);
+        if !self.mode.keep(escaped) || (escaped && len < self.min_length) {
+            return None;
+        }
+
+        let end = len.min(self.max_iter);
+        if self.min_iter < end {
+            Some(self.min_iter..end)
+        } else {
+            None
+        }
+    }
+
+    /// The points of a traced orbit that are plotted into a channel with the given loop limit, if any.
+    pub fn channel_points<'p>(&self, points: &'p [Complex64], escaped: Option<usize>, limit: u32) -> Option<&'p [Complex64]> {
+        self.range(points.len(), escaped, limit).map(|range| &points[range])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Filter, OrbitMode};
+    use num::complex::Complex64;
+
+    #[test]
+    fn modes_keep_orbits_by_whether_they_escaped() {
+        assert_eq!(Filter::new(OrbitMode::Buddha).range(10, Some(10), 100), Some(0..10));
+        assert_eq!(Filter::new(OrbitMode::Buddha).range(100, None, 100), None);
+        assert_eq!(Filter::new(OrbitMode::Anti).range(10, Some(10), 100), None);
+        assert_eq!(Filter::new(OrbitMode::Anti).range(100, None, 100), Some(0..100));
+        assert_eq!(Filter::new(OrbitMode::All).range(10, Some(10), 100), Some(0..10));
+    }
+
+    #[test]
+    fn orbits_only_escape_within_each_channels_limit() {
+        let buddha = Filter::new(OrbitMode::Buddha);
+        let anti = Filter::new(OrbitMode::Anti);
+
+        // Escaped after 300 points: bounded as far as a channel with a limit of 200 knows.
+        assert_eq!(buddha.range(300, Some(300), 500), Some(0..300));
+        assert_eq!(buddha.range(300, Some(300), 200), None);
+        assert_eq!(anti.range(300, Some(300), 200), Some(0..200));
+    }
+
+    #[test]
+    fn windows_cut_the_plotted_points() {
+        let window = Filter { min_iter: 5, max_iter: 20, ..Filter::new(OrbitMode::All) };
+
+        assert_eq!(window.range(50, None, 100), Some(5..20));
+        assert_eq!(window.range(12, Some(12), 100), Some(5..12));
+        assert_eq!(window.range(50, None, 10), Some(5..10));
+        assert_eq!(window.range(4, Some(4), 100), None);
+    }
+
+    #[test]
+    fn short_escaping_orbits_are_dropped() {
+        let long = Filter { min_length: 10, ..Filter::new(OrbitMode::Buddha) };
+        let all = Filter { min_length: 10, ..Filter::new(OrbitMode::All) };
+
+        assert_eq!(long.range(9, Some(9), 100), None);
+        assert_eq!(long.range(10, Some(10), 100), Some(0..10));
+        // Bounded orbits are never short.
+        assert_eq!(all.range(5, None, 5), Some(0..5));
+    }
+
+    #[test]
+    fn channel_points_are_the_plotted_range() {
+        let points: Vec<_> = (0..8).map(|i| Complex64::new(i as f64, 0.0)).collect();
+        let window = Filter { min_iter: 2, max_iter: 5, ..Filter::new(OrbitMode::Buddha) };
+
+        assert_eq!(window.channel_points(&points, Some(8), 100), Some(&points[2..5]));
+        assert_eq!(window.channel_points(&points, None, 100), None);
+    }
+}
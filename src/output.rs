@@ -0,0 +1,268 @@
+use image::{ColorType, ImageBuffer, Rgba};
+use image::bmp::BMPEncoder;
+use image::png::PNGEncoder;
+
+use args::ParseError;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The image formats renders can be saved as.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Format {
+    Png,
+    Tiff,
+    Ppm,
+    Bmp,
+}
+
+impl Format {
+    /// Detects the format from the extension of `path`.
+    pub fn from_path(path: &str) -> Result<Format, String> {
+        let ext = Path::new(path).extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+
+        match ext.as_str() {
+            "png" => Ok(Format::Png),
+            "tif" | "tiff" => Ok(Format::Tiff),
+            "ppm" => Ok(Format::Ppm),
+            "bmp" => Ok(Format::Bmp),
+            _ => Err(format!("Unknown image format for {}, expected .png, .tiff, .ppm or .bmp", path)),
+        }
+    }
+
+    pub fn supports_16bit(&self) -> bool {
+        *self != Format::Bmp
+    }
+}
+
+/// Checks images can be saved to `path` with `bit_depth` bits per sample.
+pub fn check_bit_depth(path: &str, bit_depth: u8) -> Result<(), String> {
+    let format = Format::from_path(path)?;
+    if !(bit_depth == 8 || (bit_depth == 16 && format.supports_16bit())) {
+        return Err("The bit depth must be 8, or 16 for .png, .tiff and .ppm images.".to_string());
+    }
+
+    Ok(())
+}
+
+/// Interleaved RGB samples of an image to save.
+#[derive(Debug, Copy, Clone)]
+pub enum Samples<'a> {
+    U8(&'a [u8]),
+    U16(&'a [u16]),
+}
+
+impl<'a> Samples<'a> {
+    fn bits(&self) -> u16 {
+        match *self {
+            Samples::U8(_) => 8,
+            Samples::U16(_) => 16,
+        }
+    }
+
+    /// The samples as bytes, with 16-bit samples big-endian.
+    fn be_bytes(&self) -> Vec<u8> {
+        match *self {
+            Samples::U8(s) => s.to_vec(),
+            Samples::U16(s) => s.iter().flat_map(|&v| vec![(v >> 8) as u8, v as u8]).collect(),
+        }
+    }
+}
+
+/// Saves an RGB image in the format given by the extension of `path`.
+pub fn save(path: &str, width: u32, height: u32, samples: Samples) -> Result<(), String> {
+    let format = Format::from_path(path)?;
+    if samples.bits() == 16 && !format.supports_16bit() {
+        return Err(format!("{} can't be saved with 16 bits per sample", path));
+    }
+
+    let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+    let mut out = BufWriter::new(file);
+
+    let res = match format {
+        Format::Png => PNGEncoder::new(&mut out).encode(&samples.be_bytes(), width, height, ColorType::RGB(samples.bits() as u8)),
+        Format::Bmp => BMPEncoder::new(&mut out).encode(&samples.be_bytes(), width, height, ColorType::RGB(8)),
+        Format::Ppm => {
+            let max = if samples.bits() == 16 { 65535 } else { 255 };
+            write!(out, "P6\n{} {}\n{}\n", width, height, max).and_then(|_| out.write_all(&samples.be_bytes()))
+        },
+        Format::Tiff => write_tiff(&mut out, width, height, samples),
+    };
+
+    res.and_then(|_| out.flush()).map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+/// Writes an uncompressed, little-endian baseline TIFF.
+fn write_tiff<W: Write>(out: &mut W, width: u32, height: u32, samples: Samples) -> ::std::io::Result<()> {
+    let bits = samples.bits();
+    let data: Vec<u8> = match samples {
+        Samples::U8(s) => s.to_vec(),
+        Samples::U16(s) => s.iter().flat_map(|&v| vec![v as u8, (v >> 8) as u8]).collect(),
+    };
+
+    // Header, then the bits per sample values, then the image data, then the directory.
+    let bits_offset = 8_u32;
+    let data_offset = bits_offset + 6;
+    let ifd_offset = data_offset + data.len() as u32 + (data.len() as u32 & 1);
+
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+    let entries: [(u16, u16, u32, u32); 9] = [
+        (256, LONG, 1, width),
+        (257, LONG, 1, height),
+        (258, SHORT, 3, bits_offset),
+        (259, SHORT, 1, 1),
+        (262, SHORT, 1, 2),
+        (273, LONG, 1, data_offset),
+        (277, SHORT, 1, 3),
+        (278, LONG, 1, height),
+        (279, LONG, 1, data.len() as u32),
+    ];
+
+    out.write_all(b"II")?;
+    out.write_all(&le16(42))?;
+    out.write_all(&le32(ifd_offset))?;
+
+    for _ in 0..3 {
+        out.write_all(&le16(bits))?;
+    }
+
+    out.write_all(&data)?;
+    if data.len() & 1 == 1 {
+        out.write_all(&[0])?;
+    }
+
+    out.write_all(&le16(entries.len() as u16))?;
+    for &(tag, kind, count, value) in entries.iter() {
+        out.write_all(&le16(tag))?;
+        out.write_all(&le16(kind))?;
+        out.write_all(&le32(count))?;
+
+        // Single shorts are left-aligned in the value field.
+        if kind == SHORT && count == 1 {
+            out.write_all(&le16(value as u16))?;
+            out.write_all(&le16(0))?;
+        } else {
+            out.write_all(&le32(value))?;
+        }
+    }
+
+    out.write_all(&le32(0))
+}
+
+fn le16(v: u16) -> [u8; 2] {
+    [v as u8, (v >> 8) as u8]
+}
+
+fn le32(v: u32) -> [u8; 4] {
+    [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+}
+
+/// Converts red, green and blue brightness from 0 to 1 into 16-bit samples.
+pub fn to_u16(rgb: &[[f64; 3]]) -> Vec<u16> {
+    rgb.iter()
+        .flat_map(|px| vec![px[0], px[1], px[2]])
+        .map(|v| (if v > 0.0 { v.min(1.0) } else { 0.0 } * 65535.0) as u16)
+        .collect()
+}
+
+/// Converts red, green and blue brightness from 0 to 1, in row order, into an 8-bit image.
+pub fn to_image(width: u32, height: u32, rgb: &[[f64; 3]]) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+    let mut buffer = ImageBuffer::from_pixel(width, height, Rgba([0, 0, 0, 255]));
+    for (p, px) in buffer.pixels_mut().zip(rgb.iter()) {
+        *p = Rgba([(px[0].min(1.0) * 255.0) as u8, (px[1].min(1.0) * 255.0) as u8, (px[2].min(1.0) * 255.0) as u8, 255]);
+    }
+
+    buffer
+}
+
+/// The 8-bit samples of an image, without its alpha.
+pub fn rgb_bytes(buffer: &ImageBuffer<Rgba<u8>, Vec<u8>>) -> Vec<u8> {
+    buffer.pixels().flat_map(|p| vec![p.data[0], p.data[1], p.data[2]]).collect()
+}
+
+/// How viewer screenshots are named, so that they don't overwrite each other.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Naming {
+    /// Always the output path, overwriting the last screenshot.
+    Overwrite,
+    /// The first free numbered path, e.g. `out_0001.png`.
+    Numbered,
+    /// The time in milliseconds, e.g. `out_1500000000000.png`, numbered after that if two
+    /// screenshots are saved in the same millisecond.
+    Timestamped,
+}
+
+impl FromStr for Naming {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Naming, ParseError> {
+        match s.trim().to_lowercase().as_str() {
+            "overwrite" => Ok(Naming::Overwrite),
+            "numbered" => Ok(Naming::Numbered),
+            "timestamped" => Ok(Naming::Timestamped),
+            _ => Err(format!("Unknown naming: {}", s).into()),
+        }
+    }
+}
+
+/// Inserts a suffix before the extension of `path`.
+pub fn with_suffix(path: &str, suffix: &str) -> String {
+    let p = Path::new(path);
+    let stem = p.file_stem().and_then(|s| s.to_str()).unwrap_or("out");
+    let name = match p.extension().and_then(|e| e.to_str()) {
+        Some(ext) => format!("{}_{}.{}", stem, suffix, ext),
+        None => format!("{}_{}", stem, suffix),
+    };
+
+    p.with_file_name(name).to_string_lossy().into_owned()
+}
+
+/// The path to save the next screenshot to.
+pub fn screenshot_path(path: &str, naming: Naming) -> String {
+    match naming {
+        Naming::Overwrite => path.to_string(),
+        Naming::Numbered => (1..)
+            .map(|i| with_suffix(path, &format!("{:04}", i)))
+            .find(|p| !Path::new(p).exists())
+            .unwrap(),
+        Naming::Timestamped => {
+            let millis = SystemTime::now().duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() * 1000 + d.subsec_millis() as u64)
+                .unwrap_or(0);
+
+            (0..)
+                .map(|i| match i {
+                    0 => with_suffix(path, &millis.to_string()),
+                    i => with_suffix(path, &format!("{}_{}", millis, i)),
+                })
+                .find(|p| !Path::new(p).exists())
+                .unwrap()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{screenshot_path, Naming};
+
+    use std::env;
+    use std::fs::{self, File};
+
+    #[test]
+    fn timestamped_screenshots_are_unique() {
+        let dir = env::temp_dir().join("rpixi-timestamped");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.png").to_string_lossy().into_owned();
+
+        let first = screenshot_path(&path, Naming::Timestamped);
+        File::create(&first).unwrap();
+        let second = screenshot_path(&path, Naming::Timestamped);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(first != second);
+    }
+}
@@ -0,0 +1,71 @@
+use image::{ImageBuffer, Rgba};
+use imageproc::drawing::draw_text_mut;
+use rusttype::{Font, FontCollection, Scale};
+
+use raw;
+
+/// The config fields drawn over images: what was rendered, and how it was sampled and mapped.
+const SUMMARY: [&str; 15] = [
+    "formula", "power", "mode", "loop_limit", "channels", "slice", "zoom", "centre_re", "centre_im",
+    "sampler", "samples", "seed", "metropolis", "merged", "curve",
+];
+
+/// Draws a status line and a summary of a render's configuration over images.
+pub struct Overlay {
+    font: Font<'static>,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        let font = Vec::from(include_bytes!("SourceSansPro-Light.ttf") as &[u8]);
+
+        Overlay {
+            font: FontCollection::from_bytes(font).into_font().unwrap(),
+        }
+    }
+
+    /// Draws `status` in the top left corner, with the summary of `config`, as printed with `{:#?}`,
+    /// below it.
+    pub fn draw(&self, buffer: &mut ImageBuffer<Rgba<u8>, Vec<u8>>, status: &str, config: &str) {
+        let scale = Scale { x: 12.4 * 2.0, y: 12.4 };
+        draw_text_mut(buffer, Rgba([255, 255, 255, 255]), 10, 10, scale, &self.font, status);
+
+        for (i, l) in summary(config).lines().enumerate() {
+            draw_text_mut(buffer, Rgba([255, 255, 255, 255]), 10, 20 + i as u32*13, scale, &self.font, l);
+        }
+    }
+}
+
+impl Default for Overlay {
+    fn default() -> Overlay {
+        Overlay::new()
+    }
+}
+
+/// The `SUMMARY` fields that `config` has, one per line, with multi-line values joined onto it.
+pub fn summary(config: &str) -> String {
+    let fields = raw::fields(config);
+    let mut lines = vec![];
+
+    for name in SUMMARY.iter() {
+        if let Some(field) = fields.iter().find(|f| f.0 == *name) {
+            let value = field.1.split_whitespace().collect::<Vec<_>>().join(" ");
+            let value = value.replace("( ", "(").replace("[ ", "[").replace(", )", ")").replace(", ]", "]");
+            lines.push(format!("{}: {}", name, value.trim_end_matches(',')));
+        }
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::summary;
+
+    #[test]
+    fn summaries_only_show_the_main_settings() {
+        let config = "Config {\n    width: 1280,\n    power: 2.0,\n    channels: Some(\n        List(\n            [\n                5000,\n                500,\n            ],\n        ),\n    ),\n    output: \"out.png\",\n    formula: Mandelbrot,\n}";
+
+        assert_eq!(summary(config), "formula: Mandelbrot\npower: 2.0\nchannels: Some(List([5000, 500]))");
+    }
+}
@@ -0,0 +1,315 @@
+use args::ParseError;
+
+use std::f64::consts::PI;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+
+/// How colours are blended across a gradient segment, following the GIMP gradient types.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Blend {
+    Linear,
+    Curved,
+    Sine,
+    SphereIncreasing,
+    SphereDecreasing,
+    Step,
+}
+
+/// A span of the gradient between two colours, with a midpoint where the blend is half way.
+#[derive(Debug, Copy, Clone)]
+struct Segment {
+    left: f64,
+    mid: f64,
+    right: f64,
+    from: [f64; 3],
+    to: [f64; 3],
+    blend: Blend,
+}
+
+impl Segment {
+    /// How far along the blend from `from` to `to` the position `t` is.
+    fn factor(&self, t: f64) -> f64 {
+        let len = self.right - self.left;
+        if len <= 0.0 {
+            return 0.0;
+        }
+
+        let pos = (t - self.left) / len;
+        let mid = (self.mid - self.left) / len;
+
+        let linear = if pos <= mid {
+            if mid > 0.0 { 0.5 * pos / mid } else { 0.0 }
+        } else if mid < 1.0 {
+            0.5 + 0.5 * (pos - mid) / (1.0 - mid)
+        } else {
+            1.0
+        };
+
+        match self.blend {
+            Blend::Linear => linear,
+            Blend::Curved => {
+                if mid > 0.0 && mid < 1.0 {
+                    pos.powf(0.5_f64.ln() / mid.ln())
+                } else {
+                    linear
+                }
+            },
+            Blend::Sine => ((PI * linear - PI / 2.0).sin() + 1.0) / 2.0,
+            Blend::SphereIncreasing => (1.0 - (linear - 1.0) * (linear - 1.0)).sqrt(),
+            Blend::SphereDecreasing => 1.0 - (1.0 - linear * linear).sqrt(),
+            Blend::Step => if pos >= mid { 1.0 } else { 0.0 },
+        }
+    }
+}
+
+/// A gradient mapping brightness in [0, 1] to a colour.
+#[derive(Clone)]
+pub struct Palette {
+    name: String,
+    segments: Vec<Segment>,
+}
+
+impl fmt::Debug for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Palette({})", self.name)
+    }
+}
+
+/// Built-in palettes as colour stops, evenly spaced from black to the brightest colour.
+const PRESETS: &[(&str, &[u32])] = &[
+    ("grey", &[0x000000, 0xFFFFFF]),
+    ("fire", &[0x000000, 0x800000, 0xFF4000, 0xFFC000, 0xFFFFFF]),
+    ("ice", &[0x000000, 0x001040, 0x0060C0, 0x40E0FF, 0xFFFFFF]),
+    ("nebula", &[0x000000, 0x301050, 0xA02060, 0xF0A040, 0xFFFFE0]),
+    ("viridis", &[0x440154, 0x3B528B, 0x21918C, 0x5EC962, 0xFDE725]),
+];
+
+impl Palette {
+    /// Builds a palette blending linearly between colour stops, given as (position, colour) pairs.
+    pub fn from_stops(name: &str, stops: &[(f64, [f64; 3])]) -> Result<Palette, String> {
+        if stops.len() < 2 {
+            return Err(format!("Palette {} needs at least two stops", name));
+        }
+
+        if stops.windows(2).any(|w| w[1].0 < w[0].0) {
+            return Err(format!("Palette {} has stops out of order", name));
+        }
+
+        let segments = stops.windows(2)
+            .map(|w| Segment {
+                left: w[0].0,
+                mid: (w[0].0 + w[1].0) / 2.0,
+                right: w[1].0,
+                from: w[0].1,
+                to: w[1].1,
+                blend: Blend::Linear,
+            })
+            .collect();
+
+        Ok(Palette {
+            name: name.to_string(),
+            segments,
+        })
+    }
+
+    pub fn preset(name: &str) -> Option<Palette> {
+        PRESETS.iter().find(|&&(n, _)| n == name).map(|&(n, colours)| {
+            let last = (colours.len() - 1) as f64;
+            let stops: Vec<_> = colours.iter().enumerate()
+                .map(|(i, &c)| (i as f64 / last, [
+                    ((c >> 16) & 0xFF) as f64 / 255.0,
+                    ((c >> 8) & 0xFF) as f64 / 255.0,
+                    (c & 0xFF) as f64 / 255.0,
+                ]))
+                .collect();
+
+            Palette::from_stops(n, &stops).expect("Invalid preset palette")
+        })
+    }
+
+    /// Parses colour stops, one `position,r,g,b` per line with colours from 0 to 255.
+    ///
+    /// Blank lines and lines starting with `#` are skipped.
+    pub fn from_csv(name: &str, text: &str) -> Result<Palette, String> {
+        let mut stops = vec![];
+
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let vals = line.split(',')
+                .map(|v| v.trim().parse::<f64>().map_err(|_| format!("Invalid number in palette: {}", v)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if vals.len() != 4 {
+                return Err(format!("Expected position,r,g,b in palette, got: {}", line));
+            }
+
+            stops.push((vals[0], [vals[1] / 255.0, vals[2] / 255.0, vals[3] / 255.0]));
+        }
+
+        Palette::from_stops(name, &stops)
+    }
+
+    /// Parses a GIMP gradient. Alpha is ignored, and HSV segments are blended in RGB.
+    pub fn from_ggr(name: &str, text: &str) -> Result<Palette, String> {
+        let mut lines = text.lines().map(str::trim);
+
+        if lines.next() != Some("GIMP Gradient") {
+            return Err(format!("{} is not a GIMP gradient", name));
+        }
+
+        let mut line = lines.next().unwrap_or("");
+        if line.starts_with("Name:") {
+            line = lines.next().unwrap_or("");
+        }
+
+        let count: usize = line.parse().map_err(|_| format!("Invalid segment count in gradient: {}", line))?;
+        let mut segments = Vec::with_capacity(count);
+
+        for line in lines.take(count) {
+            let vals = line.split_whitespace()
+                .map(|v| v.parse::<f64>().map_err(|_| format!("Invalid number in gradient: {}", v)))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            if vals.len() < 13 {
+                return Err(format!("Expected at least 13 values per gradient segment, got: {}", line));
+            }
+
+            let blend = match vals[11] as u32 {
+                0 => Blend::Linear,
+                1 => Blend::Curved,
+                2 => Blend::Sine,
+                3 => Blend::SphereIncreasing,
+                4 => Blend::SphereDecreasing,
+                5 => Blend::Step,
+                b => return Err(format!("Unknown gradient blend type: {}", b)),
+            };
+
+            segments.push(Segment {
+                left: vals[0],
+                mid: vals[1],
+                right: vals[2],
+                from: [vals[3], vals[4], vals[5]],
+                to: [vals[7], vals[8], vals[9]],
+                blend,
+            });
+        }
+
+        if segments.len() != count || count == 0 {
+            return Err(format!("Expected {} segments in gradient, got {}", count, segments.len()));
+        }
+
+        Ok(Palette {
+            name: name.to_string(),
+            segments,
+        })
+    }
+
+    /// Loads a palette from a `.csv` stop list or a GIMP `.ggr` gradient.
+    pub fn open(path: &str) -> Result<Palette, String> {
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|e| format!("Unable to read palette {}: {}", path, e))?;
+
+        if path.to_lowercase().ends_with(".ggr") {
+            Palette::from_ggr(path, &text)
+        } else {
+            Palette::from_csv(path, &text)
+        }
+    }
+
+    /// The colour at `t`, clamped to [0, 1], with each component from 0 to 1.
+    pub fn colour(&self, t: f64) -> [f64; 3] {
+        let t = if t > 0.0 { t.min(1.0) } else { 0.0 };
+
+        let seg = self.segments.iter()
+            .find(|s| t <= s.right)
+            .unwrap_or_else(|| self.segments.last().unwrap());
+
+        let f = seg.factor(t.max(seg.left));
+        let mut rgb = [0.0; 3];
+
+        for (i, c) in rgb.iter_mut().enumerate() {
+            *c = seg.from[i] + (seg.to[i] - seg.from[i]) * f;
+        }
+
+        rgb
+    }
+}
+
+impl FromStr for Palette {
+    type Err = ParseError;
+
+    /// Parses a preset name, or otherwise the path of a palette file.
+    fn from_str(s: &str) -> Result<Palette, ParseError> {
+        match Palette::preset(&s.trim().to_lowercase()) {
+            Some(palette) => Ok(palette),
+            None => {
+                let names: Vec<_> = PRESETS.iter().map(|&(n, _)| n).collect();
+                Palette::open(s).map_err(|e| ParseError(format!("{}; the presets are {}", e, names.join(", "))))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Palette;
+
+    fn assert_colour(palette: &Palette, t: f64, rgb: [f64; 3]) {
+        let c = palette.colour(t);
+        assert!(c.iter().zip(rgb.iter()).all(|(a, b)| (a - b).abs() < 1e-9), "colour at {} was {:?}, expected {:?}", t, c, rgb);
+    }
+
+    #[test]
+    fn csv_stops_blend_linearly() {
+        let palette = Palette::from_csv("test", "# position,r,g,b\n0,0,0,0\n\n0.5, 255, 0, 0\n1,255,255,255\n").unwrap();
+
+        assert_colour(&palette, 0.0, [0.0, 0.0, 0.0]);
+        assert_colour(&palette, 0.25, [0.5, 0.0, 0.0]);
+        assert_colour(&palette, 0.5, [1.0, 0.0, 0.0]);
+        assert_colour(&palette, 0.75, [1.0, 0.5, 0.5]);
+        assert_colour(&palette, 2.0, [1.0, 1.0, 1.0]);
+        assert_colour(&palette, -1.0, [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn invalid_csv_is_rejected() {
+        assert!(Palette::from_csv("test", "0,0,0,0").is_err());
+        assert!(Palette::from_csv("test", "0,0,0,0\n1,255,255").is_err());
+        assert!(Palette::from_csv("test", "0,0,0,0\n1,255,x,255").is_err());
+        assert!(Palette::from_csv("test", "1,0,0,0\n0,255,255,255").is_err());
+    }
+
+    #[test]
+    fn ggr_segments_are_parsed() {
+        let ggr = "GIMP Gradient\n\
+                   Name: Test\n\
+                   2\n\
+                   0 0.25 0.5 0 0 0 1 1 0 0 1 0 0\n\
+                   0.5 0.75 1 0 0 1 1 1 1 1 1 5 0\n";
+        let palette = Palette::from_ggr("test", ggr).unwrap();
+
+        // The first segment is half way at its midpoint, and the second steps there.
+        assert_colour(&palette, 0.125, [0.25, 0.0, 0.0]);
+        assert_colour(&palette, 0.25, [0.5, 0.0, 0.0]);
+        assert_colour(&palette, 0.375, [0.75, 0.0, 0.0]);
+        assert_colour(&palette, 0.7, [0.0, 0.0, 1.0]);
+        assert_colour(&palette, 0.8, [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn ggr_name_line_is_optional() {
+        assert!(Palette::from_ggr("test", "GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1 0 0").is_ok());
+    }
+
+    #[test]
+    fn invalid_ggr_is_rejected() {
+        assert!(Palette::from_ggr("test", "0,0,0,0\n1,255,255,255").is_err());
+        assert!(Palette::from_ggr("test", "GIMP Gradient\n2\n0 0.5 1 0 0 0 1 1 1 1 1 0 0").is_err());
+        assert!(Palette::from_ggr("test", "GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1").is_err());
+        assert!(Palette::from_ggr("test", "GIMP Gradient\n1\n0 0.5 1 0 0 0 1 1 1 1 1 9 0").is_err());
+        assert!(Palette::from_ggr("test", "GIMP Gradient\n0\n").is_err());
+    }
+}
@@ -0,0 +1,570 @@
+use canvas::{Accumulator, AccumulatorKind, Canvas};
+use render::Position;
+use sampler::Shard;
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+
+/// Identifies raw canvas files, and their version.
+const MAGIC: &str = "RPX1";
+
+/// Config fields that only change how the canvas is tone-mapped and saved, so may differ when a
+/// render is resumed.
+const RESUMABLE: [&str; 15] = [
+    "factor", "opacity", "curve", "palette", "auto_exposure", "channel_factors", "output", "bit_depth",
+    "no_overlay", "screenshots", "raw", "animate", "checkpoint", "checkpoint_every", "resume",
+];
+
+/// Config fields that may differ between canvases that are merged, as well as the resumable ones:
+/// how many samples were taken, and from where. Merged canvases list the renders summed into them
+/// in `merged`, instead of these.
+const MERGEABLE: [&str; 6] = ["seed", "shard", "samples", "metropolis", "chains", "merged"];
+
+/// The header of a raw canvas file.
+///
+/// A `.rpx` file starts with a text header, like a PPM image:
+///
+/// ```text
+/// RPX1
+/// <width> <height> <channels> <accumulator> <overflowed> [<next sample> <segments>]
+/// <config length in bytes>
+/// <config>
+/// ```
+///
+/// followed by the canvas values, interleaved per pixel, as little-endian integers or floats.
+/// Checkpoints also record the render's `Position`.
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    pub kind: AccumulatorKind,
+    pub overflowed: u64,
+    /// The next sample and the Metropolis–Hastings segments run, in checkpoints.
+    pub position: Option<(usize, usize)>,
+    /// The settings the canvas was rendered with, as drawn over images.
+    pub config: String,
+}
+
+impl Header {
+    fn read<R: BufRead>(r: &mut R) -> Result<Header, String> {
+        let mut line = String::new();
+        let mut next_line = |r: &mut R| -> Result<String, String> {
+            line.clear();
+            r.read_line(&mut line).map_err(|e| e.to_string())?;
+            Ok(line.trim().to_string())
+        };
+
+        match next_line(r) {
+            Ok(ref magic) if magic == MAGIC => (),
+            _ => return Err("Not a raw canvas file".to_string()),
+        }
+
+        let sizes = next_line(r)?;
+        let sizes: Vec<_> = sizes.split_whitespace().collect();
+        if sizes.len() != 5 && sizes.len() != 7 {
+            return Err("Invalid raw canvas header".to_string());
+        }
+        let invalid = |name| format!("Invalid raw canvas {}", name);
+
+        let width = sizes[0].parse().map_err(|_| invalid("width"))?;
+        let height = sizes[1].parse().map_err(|_| invalid("height"))?;
+        let channels = sizes[2].parse().map_err(|_| invalid("channels"))?;
+        let kind = sizes[3].parse()?;
+        let overflowed = sizes[4].parse().map_err(|_| invalid("overflow count"))?;
+        let position = match sizes.get(5..7) {
+            Some(pos) => Some((pos[0].parse().map_err(|_| invalid("position"))?, pos[1].parse().map_err(|_| invalid("position"))?)),
+            None => None,
+        };
+
+        // Read up to the length given rather than allocating it up front, as the file may be corrupt.
+        let len: u64 = next_line(r)?.parse().map_err(|_| invalid("config length"))?;
+        let mut config = vec![];
+        r.by_ref().take(len).read_to_end(&mut config).map_err(|e| e.to_string())?;
+        if config.len() as u64 != len {
+            return Err("Truncated raw canvas config".to_string());
+        }
+        let config = String::from_utf8(config).map_err(|_| invalid("config"))?;
+
+        Ok(Header {
+            width,
+            height,
+            channels,
+            kind,
+            overflowed,
+            position,
+            config,
+        })
+    }
+
+    /// The first field of the config that differs from `other`, apart from the `ignored` ones.
+    fn difference(&self, other: &str, ignored: &[&str]) -> Option<String> {
+        let theirs = fields(other);
+        let ours = fields(&self.config);
+
+        for &(name, ref value) in ours.iter() {
+            if ignored.contains(&name) {
+                continue;
+            }
+
+            match theirs.iter().find(|f| f.0 == name) {
+                Some(f) if f.1 == *value => (),
+                Some(f) => return Some(format!("{} differs,{} and{}", name, value.trim_end_matches(','), f.1.trim_end_matches(','))),
+                None => return Some(format!("{} is missing", name)),
+            }
+        }
+
+        let compared = |fields: &[(&str, String)]| fields.iter().filter(|f| !ignored.contains(&f.0)).count();
+        if compared(&theirs) != compared(&ours) {
+            return Some("the configs have different fields".to_string());
+        }
+
+        None
+    }
+
+    /// Checks that the canvas was rendered with the same image settings as `other`, so that the two
+    /// can be summed, returning the first difference if not.
+    pub fn compatible(&self, other: &Header) -> Result<(), String> {
+        if (self.width, self.height, self.channels) != (other.width, other.height, other.channels) {
+            return Err(format!("sizes differ, {}x{}x{} and {}x{}x{}",
+                               self.width, self.height, self.channels, other.width, other.height, other.channels));
+        }
+
+        if self.kind != other.kind {
+            return Err(format!("accumulators differ, {} and {}", self.kind, other.kind));
+        }
+
+        let ignored: Vec<_> = RESUMABLE.iter().chain(MERGEABLE.iter()).cloned().collect();
+        match self.difference(&other.config, &ignored) {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// The value of a top-level config field, if it has one.
+    fn field(&self, name: &str) -> Option<String> {
+        fields(&self.config).into_iter()
+            .find(|f| f.0 == name)
+            .map(|f| f.1.trim().trim_end_matches(',').to_string())
+    }
+
+    /// The renders summed into the canvas: those listed by a merge, or just the one it was rendered by.
+    fn parts(&self) -> Vec<Part> {
+        match self.field("merged") {
+            Some(list) => list.trim_start_matches('[').trim_end_matches(']').split(',')
+                .filter_map(|part| Part::parse(part.trim().trim_matches('"')))
+                .collect(),
+            None => vec![Part {
+                sampler: self.field("sampler").unwrap_or_else(|| "-".to_string()),
+                seed: self.field("seed").unwrap_or_else(|| "-".to_string()),
+                shard: self.field("shard").and_then(|s| s.parse().ok()).unwrap_or_else(Shard::whole),
+            }],
+        }
+    }
+
+    /// Checks that the canvas and `other` took different samples, so that summing them counts none
+    /// twice, returning why not if they may have. Merged canvases are checked render by render.
+    pub fn disjoint(&self, other: &Header) -> Result<(), String> {
+        for ours in &self.parts() {
+            for theirs in &other.parts() {
+                ours.disjoint(theirs)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The config of the sum of canvases with the given headers: the first one's, with the fields
+    /// that may differ between them replaced by a `merged` list of every render summed.
+    pub fn merged_config(headers: &[Header]) -> String {
+        let parts: Vec<_> = headers.iter().flat_map(|h| h.parts()).map(|p| format!("\"{}\"", p)).collect();
+        let mut lines: Vec<String> = vec![];
+        let mut dropping = false;
+
+        for line in headers[0].config.lines() {
+            if !line.starts_with("     ") {
+                let name = line.trim().split(':').next().unwrap_or("");
+                dropping = line.starts_with("    ") && MERGEABLE.contains(&name);
+            }
+
+            if !dropping {
+                lines.push(line.to_string());
+            }
+        }
+
+        let merged = format!("    merged: [{}],", parts.join(", "));
+        match lines.last().map(|l| l.starts_with('}')) {
+            Some(true) => {
+                let last = lines.len() - 1;
+                lines.insert(last, merged);
+            },
+            _ => lines.push(merged),
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// A render summed into a canvas, as far as which samples it took. Settings missing from a config
+/// are `-`.
+#[derive(Debug, Clone, PartialEq)]
+struct Part {
+    sampler: String,
+    seed: String,
+    shard: Shard,
+}
+
+impl Part {
+    /// Parses a render as listed by a merge.
+    fn parse(s: &str) -> Option<Part> {
+        let parts: Vec<_> = s.split_whitespace().collect();
+        if parts.len() != 3 {
+            return None;
+        }
+
+        Some(Part {
+            sampler: parts[0].to_string(),
+            seed: parts[1].to_string(),
+            shard: parts[2].parse().ok()?,
+        })
+    }
+
+    /// Renders with the same seed repeat each other's samples and chains unless they're different
+    /// shards of the same count, and grid samples don't depend on the seed at all.
+    fn disjoint(&self, other: &Part) -> Result<(), String> {
+        if !self.shard.overlaps(&other.shard) {
+            return Ok(());
+        }
+
+        if self.sampler == "Grid" || other.sampler == "Grid" {
+            return Err(format!("grid samples don't depend on the seed, so grid renders can only be merged as different shards \
+                                of the same count, not {:?} and {:?}", self.shard, other.shard));
+        }
+
+        if self.seed != "-" && self.seed == other.seed {
+            return Err(format!("both were rendered with seed {} and overlapping shards {:?} and {:?}, so share samples",
+                               self.seed, self.shard, other.shard));
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {:?}", self.sampler, self.seed, self.shard)
+    }
+}
+
+
+/// The top-level fields of a config, as names and their possibly multi-line values.
+pub fn fields(config: &str) -> Vec<(&str, String)> {
+    let mut fields: Vec<(&str, String)> = vec![];
+
+    for line in config.lines() {
+        let field = if line.starts_with("    ") && !line.starts_with("     ") {
+            let mut parts = line.trim().splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => Some((name, value.to_string())),
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        // Unindented lines open and close the config itself, rather than continuing a value.
+        match field {
+            Some(field) => fields.push(field),
+            None if line.starts_with(' ') => if let Some(last) = fields.last_mut() {
+                last.1.push_str(line);
+            },
+            None => (),
+        }
+    }
+
+    fields
+}
+
+/// Saves the canvas, and the settings it was rendered with, as a raw canvas file.
+pub fn save<T: Accumulator>(path: &str, canvas: &Canvas<T>, config: &str) -> Result<(), String> {
+    write(path, canvas, config, None)
+}
+
+fn write<T: Accumulator>(path: &str, canvas: &Canvas<T>, config: &str, position: Option<(usize, usize)>) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("Unable to create {}: {}", path, e))?;
+    let mut out = BufWriter::new(file);
+    let size = T::kind().size();
+
+    let position = match position {
+        Some((samples, segments)) => format!(" {} {}", samples, segments),
+        None => String::new(),
+    };
+
+    let res = write!(out, "{}\n{} {} {} {} {}{}\n{}\n{}",
+                     MAGIC, canvas.width(), canvas.height(), canvas.channels(), T::kind(), canvas.overflowed(), position,
+                     config.len(), config)
+        .and_then(|_| {
+            let mut bytes = Vec::with_capacity(canvas.values().len() * size);
+            for &v in canvas.values() {
+                let raw = v.to_raw();
+                bytes.extend((0..size).map(|i| (raw >> (i * 8)) as u8));
+            }
+            out.write_all(&bytes)
+        })
+        .and_then(|_| out.flush());
+
+    res.map_err(|e| format!("Unable to write {}: {}", path, e))
+}
+
+/// Reads the header of a raw canvas file, to find the accumulator type before opening it.
+pub fn open_header(path: &str) -> Result<Header, String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path, e))?;
+    Header::read(&mut BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))
+}
+
+/// Opens a raw canvas file, which must have been saved with accumulator `T`.
+pub fn open<T: Accumulator>(path: &str) -> Result<(Header, Canvas<T>), String> {
+    let file = File::open(path).map_err(|e| format!("Unable to open {}: {}", path, e))?;
+    let file_len = file.metadata().map_err(|e| format!("Unable to open {}: {}", path, e))?.len();
+    let mut r = BufReader::new(file);
+    let header = Header::read(&mut r).map_err(|e| format!("{}: {}", path, e))?;
+
+    if header.kind != T::kind() {
+        return Err(format!("{}: expected a {} accumulator, found {}", path, T::kind(), header.kind));
+    }
+
+    // Check the size the header gives against the file before allocating it.
+    let size = header.kind.size();
+    let len = (header.width as usize).checked_mul(header.height as usize)
+        .and_then(|n| n.checked_mul(header.channels))
+        .and_then(|n| n.checked_mul(size));
+    let len = match len {
+        Some(len) if len as u64 <= file_len => len,
+        _ => return Err(format!("{}: truncated canvas, {}x{}x{} values don't fit in the file",
+                                path, header.width, header.height, header.channels)),
+    };
+
+    let mut bytes = vec![0; len];
+    r.read_exact(&mut bytes).map_err(|e| format!("{}: truncated canvas: {}", path, e))?;
+
+    let data = bytes.chunks(size)
+        .map(|b| T::from_raw(b.iter().rev().fold(0, |raw, &byte| (raw << 8) | byte as u64)))
+        .collect();
+
+    let canvas = Canvas::from_values(header.width, header.height, header.channels, data, header.overflowed);
+    Ok((header, canvas))
+}
+
+/// Saves a checkpoint of a render in progress: the shared canvas, and how far it has got.
+///
+/// The canvas is only locked while it's copied, and the checkpoint replaces the last one once
+/// it's completely written, so an interrupted save leaves the last checkpoint intact.
+pub fn save_checkpoint<T: Accumulator>(path: &str, shared: &Mutex<Canvas<T>>, position: &Position, config: &str) -> Result<(), String> {
+    let (canvas, samples, segments) = {
+        let canvas = shared.lock().expect("Lock failed");
+        (canvas.clone(), position.samples.load(Ordering::SeqCst), position.segments.load(Ordering::SeqCst))
+    };
+
+    let partial = format!("{}.partial", path);
+    write(&partial, &canvas, config, Some((samples, segments)))?;
+    fs::rename(&partial, path).map_err(|e| format!("Unable to replace {}: {}", path, e))
+}
+
+/// Opens a checkpoint to resume a render with the given config, which must match the one it was
+/// saved with apart from how the result is tone-mapped and saved.
+pub fn resume<T: Accumulator>(path: &str, config: &str) -> Result<(Canvas<T>, Position), String> {
+    let (header, canvas) = open::<T>(path)?;
+
+    let (samples, segments) = match header.position {
+        Some(position) => position,
+        None => return Err(format!("{} is not a checkpoint", path)),
+    };
+
+    if let Some(e) = header.difference(config, &RESUMABLE) {
+        return Err(format!("{} was saved with different settings: {}", path, e));
+    }
+
+    Ok((canvas, Position::new(samples, segments)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn temp_path(name: &str) -> String {
+        env::temp_dir().join(format!("rpixi-{}.rpx", name)).to_string_lossy().into_owned()
+    }
+
+    fn config(fields: &[(&str, &str)]) -> String {
+        let mut config = "Config {\n".to_string();
+        for &(name, value) in fields {
+            config += &format!("    {}: {},\n", name, value);
+        }
+        config + "}"
+    }
+
+    fn header(config: String) -> Header {
+        Header {
+            width: 4,
+            height: 3,
+            channels: 2,
+            kind: AccumulatorKind::U32,
+            overflowed: 0,
+            position: None,
+            config,
+        }
+    }
+
+    fn round_trip<T: Accumulator>(values: &[f64]) {
+        let data: Vec<T> = values.iter().cycle().take(4 * 3 * 2).map(|&v| T::from_f64(v)).collect();
+        let canvas = Canvas::from_values(4, 3, 2, data, 7);
+        let path = temp_path(&format!("round-trip-{}", T::kind()));
+        let cfg = config(&[("width", "4"), ("height", "3")]);
+
+        save(&path, &canvas, &cfg).unwrap();
+        assert_eq!(open_header(&path).unwrap().kind, T::kind());
+        let (header, opened) = open::<T>(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!((header.width, header.height, header.channels, header.overflowed), (4, 3, 2, 7));
+        assert_eq!(header.position, None);
+        assert_eq!(header.config, cfg);
+        assert_eq!(opened.values(), canvas.values());
+        assert_eq!(opened.overflowed(), 7);
+    }
+
+    #[test]
+    fn canvases_round_trip() {
+        round_trip::<u16>(&[0.0, 1.0, 255.0, 256.0, 65535.0]);
+        round_trip::<u32>(&[0.0, 1.0, 65536.0, 4294967295.0]);
+        round_trip::<u64>(&[0.0, 1.0, 4294967296.0, 1e18]);
+        round_trip::<f32>(&[0.0, 0.25, 1.5e-7, 3.4e38]);
+        round_trip::<f64>(&[0.0, 0.1, 1e-300, 1e300]);
+    }
+
+    #[test]
+    fn opening_with_the_wrong_accumulator_fails() {
+        let path = temp_path("wrong-accumulator");
+        save(&path, &Canvas::<u32>::new(2, 2, 1), "").unwrap();
+        let res = open::<f32>(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn oversized_headers_are_rejected() {
+        let path = temp_path("oversized");
+
+        fs::write(&path, "RPX1\n4294967295 4294967295 1000000 f64 0\n2\n{}").unwrap();
+        let canvas = open::<f64>(&path);
+        fs::write(&path, "RPX1\n1 1 1 f64 0\n18446744073709551615\n{}").unwrap();
+        let config = open_header(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert!(canvas.is_err());
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn checkpoints_resume_from_their_position() {
+        let path = temp_path("checkpoint");
+        let cfg = config(&[("power", "2.0"), ("curve", "Exp")]);
+        let canvas = Canvas::from_values(2, 1, 1, vec![3_u32, 5], 0);
+
+        save_checkpoint(&path, &Mutex::new(canvas), &Position::new(12, 34), &cfg).unwrap();
+        let same = resume::<u32>(&path, &config(&[("power", "2.0"), ("curve", "Log")]));
+        let different = resume::<u32>(&path, &config(&[("power", "3.0"), ("curve", "Exp")]));
+        fs::remove_file(&path).unwrap();
+
+        let (resumed, position) = same.unwrap();
+        assert_eq!(resumed.values(), &[3, 5]);
+        assert_eq!(position.samples.load(Ordering::SeqCst), 12);
+        assert_eq!(position.segments.load(Ordering::SeqCst), 34);
+        assert!(different.is_err());
+    }
+
+    #[test]
+    fn difference_finds_the_first_differing_field() {
+        let ours = header(config(&[("power", "2.0"), ("loop_limit", "200"), ("curve", "Exp")]));
+
+        assert_eq!(ours.difference(&ours.config, &[]), None);
+        assert_eq!(ours.difference(&config(&[("power", "2.0"), ("loop_limit", "500"), ("curve", "Log")]), &[]),
+                   Some("loop_limit differs, 200 and 500".to_string()));
+        assert_eq!(ours.difference(&config(&[("power", "2.0"), ("loop_limit", "200"), ("curve", "Log")]), &["curve"]), None);
+        assert_eq!(ours.difference(&config(&[("power", "2.0"), ("curve", "Exp")]), &[]),
+                   Some("loop_limit is missing".to_string()));
+        assert_eq!(ours.difference(&config(&[("power", "2.0"), ("loop_limit", "200"), ("curve", "Exp"), ("skip", "1")]), &[]),
+                   Some("the configs have different fields".to_string()));
+    }
+
+    #[test]
+    fn difference_compares_multi_line_values() {
+        let list = |v: &str| format!("List(\n        [\n            {}\n        ]\n    )", v);
+        let ours = header(config(&[("channels", &list("5000"))]));
+
+        assert_eq!(ours.difference(&config(&[("channels", &list("5000"))]), &[]), None);
+        assert!(ours.difference(&config(&[("channels", &list("500"))]), &[]).is_some());
+    }
+
+    #[test]
+    fn compatible_ignores_sampling_and_output_settings() {
+        let ours = header(config(&[("power", "2.0"), ("seed", "1"), ("samples", "Some(100)"), ("output", "\"a.png\"")]));
+        let theirs = header(config(&[("power", "2.0"), ("seed", "2"), ("samples", "Some(500)"), ("output", "\"b.png\"")]));
+        assert_eq!(ours.compatible(&theirs), Ok(()));
+
+        let power = header(config(&[("power", "3.0"), ("seed", "2"), ("samples", "Some(100)"), ("output", "\"a.png\"")]));
+        assert!(ours.compatible(&power).is_err());
+
+        let size = Header { width: 8, ..theirs.clone() };
+        assert!(ours.compatible(&size).is_err());
+
+        let kind = Header { kind: AccumulatorKind::F32, ..theirs.clone() };
+        assert!(ours.compatible(&kind).is_err());
+    }
+
+    #[test]
+    fn disjoint_rejects_repeated_samples() {
+        let render = |sampler: &str, seed: &str, shard: &str| header(config(&[("sampler", sampler), ("seed", seed), ("shard", shard)]));
+        let random = render("Random", "1", "1/1");
+
+        assert!(random.disjoint(&random).is_err());
+        assert_eq!(random.disjoint(&render("Random", "2", "1/1")), Ok(()));
+        assert_eq!(render("Random", "1", "1/2").disjoint(&render("Random", "1", "2/2")), Ok(()));
+        assert!(render("Random", "1", "1/2").disjoint(&render("Random", "1", "2/3")).is_err());
+
+        let grid = render("Grid", "1", "1/1");
+        assert!(grid.disjoint(&render("Grid", "2", "1/1")).is_err());
+        assert!(grid.disjoint(&render("Random", "2", "1/1")).is_err());
+        assert_eq!(render("Grid", "1", "1/2").disjoint(&render("Grid", "1", "2/2")), Ok(()));
+    }
+
+    #[test]
+    fn merged_canvases_record_their_renders() {
+        let render = |seed: &str, shard: &str| header(config(&[("power", "2.0"), ("sampler", "Random"), ("seed", seed),
+                                                                ("shard", shard), ("samples", "Some(100)")]));
+        let (first, second) = (render("1", "1/2"), render("1", "2/2"));
+        let merged = header(Header::merged_config(&[first.clone(), second.clone()]));
+
+        assert_eq!(merged.config, "Config {\n    power: 2.0,\n    sampler: Random,\n    merged: [\"Random 1 1/2\", \"Random 1 2/2\"],\n}");
+        assert_eq!(merged.compatible(&first), Ok(()));
+
+        // Merging the sum again with one of the renders in it would count that render twice.
+        assert!(merged.disjoint(&second).is_err());
+        assert!(merged.disjoint(&merged).is_err());
+
+        let third = render("2", "1/1");
+        assert_eq!(merged.disjoint(&third), Ok(()));
+
+        let remerged = header(Header::merged_config(&[merged.clone(), third.clone()]));
+        assert_eq!(remerged.parts().len(), 3);
+        assert!(remerged.disjoint(&first).is_err());
+        assert!(remerged.disjoint(&third).is_err());
+        assert_eq!(remerged.disjoint(&render("3", "1/1")), Ok(()));
+    }
+}
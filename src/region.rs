@@ -0,0 +1,206 @@
+use num::complex::Complex64;
+
+use image;
+
+use args::ParseError;
+use sampler::Bounds;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// A mask image stretched over the sampling bounds, where light pixels are inside the region.
+#[derive(Clone)]
+pub struct Mask {
+    width: u32,
+    height: u32,
+    data: Vec<bool>,
+}
+
+impl Mask {
+    pub fn open(path: &str) -> Result<Mask, String> {
+        let img = image::open(path).map_err(|e| format!("Unable to open mask {}: {}", path, e))?.to_luma();
+
+        Ok(Mask {
+            width: img.width(),
+            height: img.height(),
+            data: img.pixels().map(|p| p.data[0] > 127).collect(),
+        })
+    }
+
+    /// Whether the point at (u, v) within the unit square is inside, with v = 0 at the top of the image.
+    fn contains(&self, u: f64, v: f64) -> bool {
+        if !(u >= 0.0 && v >= 0.0 && u < 1.0 && v < 1.0) {
+            return false;
+        }
+
+        let x = (u * self.width as f64) as usize;
+        let y = (v * self.height as f64) as usize;
+        self.data[y * self.width as usize + x]
+    }
+}
+
+impl fmt::Debug for Mask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Mask({}x{})", self.width, self.height)
+    }
+}
+
+/// The shape of the area that samples are taken from.
+#[derive(Debug, Clone)]
+pub enum Shape {
+    /// The whole of the bounds.
+    Rect,
+    Disc { centre: Complex64, radius: f64 },
+    Annulus { centre: Complex64, inner: f64, outer: f64 },
+    Mask(Mask),
+}
+
+impl FromStr for Shape {
+    type Err = ParseError;
+
+    /// Parses a shape, with its parameters after a colon: `disc:re,im,radius`, `annulus:re,im,inner,outer`
+    /// or `mask:path.png`.
+    fn from_str(s: &str) -> Result<Shape, ParseError> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let arg = parts.next();
+
+        let nums = |arg: &str, count: usize| -> Result<Vec<f64>, String> {
+            let nums = arg.split(',')
+                .map(|n| n.trim().parse().map_err(|_| format!("Invalid number in region: {}", n)))
+                .collect::<Result<Vec<f64>, String>>()?;
+
+            if nums.len() != count {
+                return Err(format!("Expected {} values for {}, got {}", count, name, nums.len()));
+            }
+
+            Ok(nums)
+        };
+
+        match (name.as_str(), arg) {
+            ("rect", None) => Ok(Shape::Rect),
+            ("disc", Some(a)) => {
+                let n = nums(a, 3)?;
+                if n[2] <= 0.0 {
+                    return Err(format!("Invalid disc radius: {}", n[2]).into());
+                }
+
+                Ok(Shape::Disc { centre: Complex64::new(n[0], n[1]), radius: n[2] })
+            },
+            ("annulus", Some(a)) => {
+                let n = nums(a, 4)?;
+                if n[2] < 0.0 || n[3] <= n[2] {
+                    return Err(format!("Invalid annulus radii: {} to {}", n[2], n[3]).into());
+                }
+
+                Ok(Shape::Annulus { centre: Complex64::new(n[0], n[1]), inner: n[2], outer: n[3] })
+            },
+            ("mask", Some(path)) => Ok(Shape::Mask(Mask::open(path)?)),
+            _ => Err(format!("Unknown region: {}", s).into()),
+        }
+    }
+}
+
+/// The part of the plane that samples are taken from.
+///
+/// Samples are drawn from `bounds` and rejected if they fall outside the shape.
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub bounds: Bounds,
+    pub shape: Shape,
+}
+
+impl Region {
+    /// Creates a region. Discs and annuli are sampled from their own bounding box, while masks are
+    /// stretched over `bounds`.
+    pub fn new(shape: Shape, bounds: Bounds) -> Region {
+        let bounds = match shape {
+            Shape::Disc { centre, radius: r } | Shape::Annulus { centre, outer: r, .. } => Bounds {
+                re_min: centre.re - r,
+                re_max: centre.re + r,
+                im_min: centre.im - r,
+                im_max: centre.im + r,
+            },
+            _ => bounds,
+        };
+
+        Region { bounds, shape }
+    }
+
+    pub fn contains(&self, c: Complex64) -> bool {
+        let b = &self.bounds;
+        if !(c.re >= b.re_min && c.re < b.re_max && c.im >= b.im_min && c.im < b.im_max) {
+            return false;
+        }
+
+        match self.shape {
+            Shape::Rect => true,
+            Shape::Disc { centre, radius } => (c - centre).norm_sqr() <= radius * radius,
+            Shape::Annulus { centre, inner, outer } => {
+                let r = (c - centre).norm_sqr();
+                r >= inner * inner && r <= outer * outer
+            },
+            Shape::Mask(ref mask) => {
+                let u = (c.re - b.re_min) / (b.re_max - b.re_min);
+                let v = (b.im_max - c.im) / (b.im_max - b.im_min);
+                mask.contains(u, v)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mask, Region, Shape};
+    use num::complex::Complex64;
+    use sampler::Bounds;
+
+    fn c(re: f64, im: f64) -> Complex64 {
+        Complex64::new(re, im)
+    }
+
+    #[test]
+    fn rectangles_include_their_minimum_bounds_only() {
+        let region = Region::new(Shape::Rect, Bounds { re_min: -2.0, re_max: 0.5, im_min: -1.0, im_max: 1.0 });
+
+        assert!(region.contains(c(-2.0, -1.0)));
+        assert!(region.contains(c(0.4, 0.9)));
+        assert!(!region.contains(c(0.5, 0.0)));
+        assert!(!region.contains(c(0.0, 1.0)));
+        assert!(!region.contains(c(f64::NAN, 0.0)));
+    }
+
+    #[test]
+    fn discs_and_annuli_are_bounded_by_their_radius() {
+        let disc = Region::new("disc:-1,0.5,0.25".parse().unwrap(), Bounds::square(2.0));
+        assert_eq!(disc.bounds, Bounds { re_min: -1.25, re_max: -0.75, im_min: 0.25, im_max: 0.75 });
+        assert!(disc.contains(c(-1.0, 0.5)));
+        assert!(disc.contains(c(-1.2, 0.5)));
+        assert!(!disc.contains(c(-1.2, 0.7)));
+
+        let annulus = Region::new("annulus:0,0,1,2".parse().unwrap(), Bounds::square(2.0));
+        assert!(!annulus.contains(c(0.5, 0.5)));
+        assert!(annulus.contains(c(1.5, 0.0)));
+        assert!(!annulus.contains(c(1.5, 1.5)));
+    }
+
+    #[test]
+    fn masks_are_stretched_over_the_bounds() {
+        // Light in the top left and bottom right quarters.
+        let mask = Mask { width: 2, height: 2, data: vec![true, false, false, true] };
+        let region = Region::new(Shape::Mask(mask), Bounds::square(1.0));
+
+        assert!(region.contains(c(-0.5, 0.5)));
+        assert!(!region.contains(c(0.5, 0.5)));
+        assert!(!region.contains(c(-0.5, -0.5)));
+        assert!(region.contains(c(0.5, -0.5)));
+    }
+
+    #[test]
+    fn invalid_shapes_are_rejected() {
+        assert!("disc:0,0".parse::<Shape>().is_err());
+        assert!("disc:0,0,-1".parse::<Shape>().is_err());
+        assert!("annulus:0,0,2,1".parse::<Shape>().is_err());
+        assert!("square".parse::<Shape>().is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use num::complex::Complex64;
+
+use rayon;
+use rayon::prelude::*;
+
+use canvas::{Accumulator, Canvas, Splat};
+use colouring::Colouring;
+use metropolis::Metropolis;
+use orbit::{Filter, Tracer};
+use sampler::Sampler;
+use viewport::Viewport;
+
+use std::ops::Range;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Samples claimed by a worker at a time.
+const BLOCK_SIZE: usize = 256;
+
+/// Orbit points traced in each round for every value of the worker canvases merged after it, so
+/// that tracing outweighs merging.
+const ROUND_POINTS_PER_VALUE: usize = 16;
+
+/// Mutations each Metropolis–Hastings chain runs between merges.
+const SEGMENT_STEPS: usize = 1024;
+
+/// How far rendering into a shared canvas has got, so that an interrupted render can be resumed.
+///
+/// Both positions only advance while the shared canvas is locked, at the same time as the round they
+/// complete is merged in, so a locked canvas always matches the positions.
+#[derive(Debug, Default)]
+pub struct Position {
+    /// The index of the next sample to render.
+    pub samples: AtomicUsize,
+    /// The number of Metropolis–Hastings segments run, over all chains.
+    pub segments: AtomicUsize,
+}
+
+impl Position {
+    pub fn new(samples: usize, segments: usize) -> Position {
+        Position {
+            samples: AtomicUsize::new(samples),
+            segments: AtomicUsize::new(segments),
+        }
+    }
+
+    /// The progress made, counted as by the render methods: one per sample, and one per mutation of each chain.
+    ///
+    /// Segment `i` is the `i / chains`th segment of chain `i % chains`, so the segments run are some
+    /// number of whole segments of every chain, then one more of the first few chains.
+    pub fn done(&self, chains: usize, steps: usize) -> usize {
+        let segments = self.segments.load(Ordering::SeqCst);
+        let chains = chains.max(1);
+        let (whole, partial) = (segments / chains, segments % chains);
+        let next = SEGMENT_STEPS.min(steps.saturating_sub(whole * SEGMENT_STEPS));
+
+        self.samples.load(Ordering::SeqCst) + chains * steps.min(whole * SEGMENT_STEPS) + partial * next
+    }
+}
+
+/// Renders orbits into a shared canvas.
+///
+/// Each worker thread accumulates into its own canvas, kept for the whole render, so the shared
+/// canvas is only locked once per round rather than per orbit. Rounds are sized by the number of
+/// orbit points traced, and after each one the worker canvases are merged into the shared one in
+/// parallel bands of rows, and cleared.
+///
+/// Setting `cancel` stops the workers between blocks of samples. The unfinished round is dropped, so
+/// the shared canvas still matches its `Position`.
+pub struct Renderer<'a> {
+    pub tracer: &'a Tracer<'a>,
+    pub view: Viewport,
+    pub limits: &'a [u32],
+    pub filter: Filter,
+    pub splat: Splat,
+    /// Colours each point into red, green and blue channels, instead of a channel per loop limit.
+    pub colouring: Option<Colouring>,
+    pub cancel: &'a AtomicBool,
+}
+
+impl<'a> Renderer<'a> {
+    /// The number of channels in the canvases rendered into.
+    pub fn channels(&self) -> usize {
+        match self.colouring {
+            Some(_) => 3,
+            None => self.limits.len(),
+        }
+    }
+
+    fn blank<T: Accumulator>(&self) -> Canvas<T> {
+        Canvas::new(self.view.width, self.view.height, self.channels())
+    }
+
+    /// Plots a traced orbit with the given weight, into each channel cut off at that channel's loop
+    /// limit, or coloured into red, green and blue.
+    fn plot<T: Accumulator>(&self, canvas: &mut Canvas<T>, points: &[Complex64], escaped: Option<usize>, weight: f64) {
+        let colouring = match self.colouring {
+            Some(ref colouring) => colouring,
+            None => {
+                for (channel, &limit) in self.limits.iter().enumerate() {
+                    if let Some(points) = self.filter.channel_points(points, escaped, limit) {
+                        canvas.plot(&self.view, points, channel, self.splat, weight);
+                    }
+                }
+                return;
+            },
+        };
+
+        if let Some(range) = self.filter.range(points.len(), escaped, colouring.limit) {
+            let points = &points[..range.end];
+
+            for n in range {
+                let (pos_x, pos_y) = self.view.position(points[n]);
+                let rgb = colouring.colour(points, n, escaped);
+
+                for (ch, &colour) in rgb.iter().enumerate() {
+                    canvas.splat(pos_x, pos_y, ch, self.splat, weight * colour);
+                }
+            }
+        }
+    }
+
+    /// Runs `work` for each index in `indices`, claimed `block` at a time, in rounds.
+    ///
+    /// `work` returns how far the index advances the progress, and the number of orbit points it
+    /// traced. Workers stop claiming indices once the round has traced enough points, so every index
+    /// before the round's end is done. After each round is merged into the shared canvas, and while
+    /// it's still locked, `publish` is called with the end of the round and the progress made.
+    fn rounds<T, F, P>(&self, indices: Range<usize>, block: usize, shared: &Mutex<Canvas<T>>, publish: P, work: F)
+        where T: Accumulator, F: Fn(usize, &mut Canvas<T>, &mut Vec<Complex64>) -> (usize, usize) + Sync, P: Fn(usize, usize)
+    {
+        let threads = rayon::current_num_threads();
+        let mut canvases: Vec<Canvas<T>> = (0..threads).map(|_| self.blank()).collect();
+        let values = self.view.width as usize * self.view.height as usize * self.channels();
+        let round_points = ROUND_POINTS_PER_VALUE * values * threads;
+        let mut start = indices.start;
+
+        while start < indices.end {
+            let next = AtomicUsize::new(start);
+            let traced = AtomicUsize::new(0);
+            let round_done = AtomicUsize::new(0);
+
+            canvases.par_iter_mut().for_each(|canvas| {
+                let mut points = vec![];
+                let mut done = 0;
+
+                while traced.load(Ordering::Relaxed) < round_points && !self.cancel.load(Ordering::Relaxed) {
+                    let from = next.fetch_add(block, Ordering::Relaxed);
+                    if from >= indices.end {
+                        break;
+                    }
+
+                    let mut block_points = 0;
+                    for i in from..indices.end.min(from + block) {
+                        let (progress, points) = work(i, canvas, &mut points);
+                        done += progress;
+                        block_points += points;
+                    }
+                    traced.fetch_add(block_points, Ordering::Relaxed);
+                }
+
+                round_done.fetch_add(done, Ordering::Relaxed);
+            });
+
+            if self.cancel.load(Ordering::SeqCst) {
+                return;
+            }
+
+            // Only publish progress once the work is visible in the shared canvas.
+            let end = indices.end.min(next.into_inner());
+            {
+                let mut shared = shared.lock().expect("Lock failed");
+                shared.merge_and_clear(&mut canvases);
+                publish(end, round_done.into_inner());
+            }
+
+            start = end;
+        }
+    }
+
+    /// Traces and plots every sample of `sampler` that falls inside its region, from the sample `position` has reached.
+    pub fn render_samples<T: Accumulator>(&self, sampler: &Sampler, shared: &Mutex<Canvas<T>>, progress: &AtomicUsize, position: &Position) {
+        let publish = |end, done| {
+            position.samples.store(end, Ordering::SeqCst);
+            progress.fetch_add(done, Ordering::SeqCst);
+        };
+
+        let start = position.samples.load(Ordering::SeqCst);
+        self.rounds(start..sampler.len(), BLOCK_SIZE, shared, publish, |i, canvas, points| {
+            match sampler.sample(i) {
+                Some(c) => {
+                    let escaped = self.tracer.trace(c, points);
+                    self.plot(canvas, points, escaped, 1.0);
+                    (1, points.len() + 1)
+                },
+                None => (1, 1),
+            }
+        });
+    }
+
+    /// Runs `chains` Metropolis–Hastings chains for `steps` mutations each, plotting the current orbit after every mutation.
+    ///
+    /// Chains resumed from `position` start again from fresh points, rather than repeating the
+    /// mutations already plotted. Each resume numbers its chains after every chain an earlier run
+    /// could have started, so their random streams never overlap. Orbits are weighted, so the canvas
+    /// needs a floating-point accumulator.
+    pub fn render_metropolis<T: Accumulator>(&self, metropolis: &Metropolis, chains: usize, steps: usize, shared: &Mutex<Canvas<T>>, progress: &AtomicUsize, position: &Position) {
+        if steps == 0 {
+            return;
+        }
+
+        let start = position.segments.load(Ordering::SeqCst);
+        let starts: Vec<_> = (0..chains).into_par_iter()
+            .map(|chain| metropolis.start(start * chains + chain, self.cancel))
+            .collect();
+        let mean = starts.iter().map(|s| s.1).sum::<f64>() / chains.max(1) as f64;
+        let states: Vec<_> = starts.into_iter().map(|s| Mutex::new(s.0)).collect();
+        let segments = (steps as f64 / SEGMENT_STEPS as f64).ceil() as usize;
+
+        let publish = |end, done| {
+            position.segments.store(end, Ordering::SeqCst);
+            progress.fetch_add(done, Ordering::SeqCst);
+        };
+
+        // A chain's segments may be claimed by different workers, but its lock runs them one at a
+        // time, each continuing from where the last left off.
+        self.rounds(start..chains * segments, 1, shared, publish, |i, canvas, _| {
+            let (chain, segment) = (i % chains, i / chains);
+            let steps = SEGMENT_STEPS.min(steps - segment * SEGMENT_STEPS);
+
+            let points = match *states[chain].lock().expect("Lock failed") {
+                Some(ref mut chain) => metropolis.run(chain, steps, mean, |points, escaped, weight| self.plot(canvas, points, escaped, weight)),
+                None => steps,
+            };
+
+            // Chains that never found a visible orbit still count towards the total.
+            (steps, points)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Position, Renderer, SEGMENT_STEPS};
+    use canvas::{Canvas, Splat};
+    use formula::Mandelbrot;
+    use metropolis::Metropolis;
+    use orbit::{Filter, OrbitMode, Tracer};
+    use region::{Region, Shape};
+    use sampler::{Bounds, Shard};
+    use slice::{Axis, Slice};
+    use viewport::Viewport;
+
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[test]
+    fn progress_counts_partly_run_segments() {
+        // Three chains of two and a bit segments each.
+        let steps = 2 * SEGMENT_STEPS + 100;
+
+        assert_eq!(Position::new(5, 0).done(3, steps), 5);
+        assert_eq!(Position::new(5, 4).done(3, steps), 5 + 4 * SEGMENT_STEPS);
+        assert_eq!(Position::new(0, 7).done(3, steps), 6 * SEGMENT_STEPS + 100);
+        assert_eq!(Position::new(0, 9).done(3, steps), 3 * steps);
+    }
+
+    #[test]
+    fn resumed_chains_finish_their_progress() {
+        let formula = Mandelbrot { power: 2.0 };
+        let tracer = Tracer {
+            formula: &formula,
+            slice: Slice { sampled: (Axis::CRe, Axis::CIm), origin: [0.0; 4], angle: 0.0 },
+            loop_limit: 50,
+            bailout: 2.0,
+            skip: 1,
+        };
+        let view = Viewport::new(16, 16, 4.0);
+        let region = Region::new(Shape::Rect, Bounds::square(2.0));
+        let limits = [50];
+        let cancel = AtomicBool::new(false);
+
+        let metropolis = Metropolis {
+            tracer: &tracer,
+            view,
+            region: &region,
+            limits: &limits,
+            filter: Filter::new(OrbitMode::Buddha),
+            seed: 1,
+            shard: Shard::whole(),
+        };
+        let renderer = Renderer {
+            tracer: &tracer,
+            view,
+            limits: &limits,
+            filter: Filter::new(OrbitMode::Buddha),
+            splat: Splat::None,
+            colouring: None,
+            cancel: &cancel,
+        };
+
+        // Resumed part of the way through the first round of segments.
+        let (chains, steps) = (3, 2 * SEGMENT_STEPS + 100);
+        let position = Position::new(0, 4);
+        let progress = AtomicUsize::new(position.done(chains, steps));
+        let shared = Mutex::new(Canvas::<f32>::new(16, 16, 1));
+
+        renderer.render_metropolis(&metropolis, chains, steps, &shared, &progress, &position);
+
+        assert_eq!(position.segments.load(Ordering::SeqCst), 9);
+        assert_eq!(progress.load(Ordering::SeqCst), chains * steps);
+        assert_eq!(position.done(chains, steps), chains * steps);
+    }
+}
@@ -0,0 +1,367 @@
+use num::complex::Complex64;
+
+use args::ParseError;
+use region::Region;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// How the sample points are distributed over the bounds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SamplerKind {
+    /// A regular grid spaced by `delta`.
+    Grid,
+    /// Uniformly random points.
+    Random,
+    /// One random point in each cell of a grid sized to the sample count.
+    Jittered,
+    /// The Halton sequence in bases 2 and 3.
+    Halton,
+    /// The first two dimensions of the Sobol sequence.
+    Sobol,
+}
+
+impl FromStr for SamplerKind {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<SamplerKind, ParseError> {
+        match s.trim().to_lowercase().as_str() {
+            "grid" => Ok(SamplerKind::Grid),
+            "random" => Ok(SamplerKind::Random),
+            "jittered" | "stratified" => Ok(SamplerKind::Jittered),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!("Unknown sampler: {}", s).into()),
+        }
+    }
+}
+
+/// One of `count` interleaved parts of the samples, so a render can be split across machines.
+///
+/// Shard `index` takes every `count`th sample starting from sample `index - 1`, so the shards of
+/// renders with the same seed take the whole render's samples between them.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Shard {
+    /// From 1 to `count`.
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    /// The shard holding every sample.
+    pub fn whole() -> Shard {
+        Shard { index: 1, count: 1 }
+    }
+
+    /// Whether the two shards share any samples.
+    pub fn overlaps(&self, other: &Shard) -> bool {
+        self.count != other.count || self.index == other.index
+    }
+}
+
+impl FromStr for Shard {
+    type Err = ParseError;
+
+    /// Parses a shard as `index/count`, e.g. `2/4`.
+    fn from_str(s: &str) -> Result<Shard, ParseError> {
+        let mut parts = s.splitn(2, '/').map(|p| p.trim().parse::<usize>());
+
+        match (parts.next(), parts.next()) {
+            (Some(Ok(index)), Some(Ok(count))) if index >= 1 && index <= count => Ok(Shard { index, count }),
+            _ => Err(format!("Invalid shard, expected index/count from 1/n to n/n: {}", s).into()),
+        }
+    }
+}
+
+/// Written as it's parsed, so configs saved with raw canvases can be compared shard by shard.
+impl fmt::Debug for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// The rectangle of the plane that samples are taken from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Bounds {
+    pub re_min: f64,
+    pub re_max: f64,
+    pub im_min: f64,
+    pub im_max: f64,
+}
+
+impl Bounds {
+    /// The square from -bounds to bounds on both axes.
+    pub fn square(bounds: f64) -> Bounds {
+        Bounds {
+            re_min: -bounds,
+            re_max: bounds,
+            im_min: -bounds,
+            im_max: bounds,
+        }
+    }
+
+    fn lerp(&self, u: f64, v: f64) -> Complex64 {
+        Complex64::new(self.re_min + u * (self.re_max - self.re_min), self.im_min + v * (self.im_max - self.im_min))
+    }
+}
+
+/// Generates sample points on demand from their index.
+///
+/// Every sample depends only on its index and the seed, so renders are reproducible
+/// no matter how the samples are split between threads.
+#[derive(Debug, Clone)]
+pub struct Sampler<'a> {
+    kind: SamplerKind,
+    region: &'a Region,
+    delta: f64,
+    seed: u64,
+    shard: Shard,
+    len: usize,
+    cols: usize,
+    rows: usize,
+}
+
+impl<'a> Sampler<'a> {
+    /// Creates a sampler over the bounds of `region`, taking the samples of `shard`. When `samples`
+    /// is `None`, the sample count matches the grid spaced by `delta`, which must be positive. The
+    /// grid sampler is always spaced by `delta`, so callers should reject a sample count for it.
+    ///
+    /// The jittered sampler takes exactly `samples` points too, so when they don't fill its grid of
+    /// cells the last column is only partly sampled.
+    pub fn new(kind: SamplerKind, region: &'a Region, delta: f64, samples: Option<usize>, seed: u64, shard: Shard) -> Sampler<'a> {
+        let bounds = region.bounds;
+
+        let (cols, rows) = match (kind, samples) {
+            (SamplerKind::Grid, _) | (_, None) => {
+                (grid_steps(bounds.re_min, bounds.re_max, delta), grid_steps(bounds.im_min, bounds.im_max, delta))
+            },
+            (SamplerKind::Jittered, Some(n)) => {
+                let aspect = (bounds.re_max - bounds.re_min) / (bounds.im_max - bounds.im_min);
+                let cols = ((n as f64 * aspect).sqrt().ceil() as usize).max(1);
+                (cols, (n as f64 / cols as f64).ceil() as usize)
+            },
+            (_, Some(n)) => (n, 1),
+        };
+
+        Sampler {
+            kind,
+            region,
+            delta,
+            seed,
+            shard,
+            len: (cols * rows).min(samples.unwrap_or(usize::MAX)),
+            cols,
+            rows,
+        }
+    }
+
+    /// The number of samples in the shard.
+    pub fn len(&self) -> usize {
+        (self.len + self.shard.count - self.shard.index) / self.shard.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The sample of the shard with the given index, or `None` if it falls outside the region.
+    pub fn sample(&self, i: usize) -> Option<Complex64> {
+        let c = self.point(i * self.shard.count + self.shard.index - 1);
+
+        if self.region.contains(c) {
+            Some(c)
+        } else {
+            None
+        }
+    }
+
+    fn point(&self, i: usize) -> Complex64 {
+        let b = &self.region.bounds;
+
+        match self.kind {
+            SamplerKind::Grid => {
+                let (col, row) = (i / self.rows, i % self.rows);
+                Complex64::new(b.re_min + col as f64 * self.delta, b.im_min + row as f64 * self.delta)
+            },
+            SamplerKind::Random => {
+                b.lerp(unit(hash(self.seed, 2 * i as u64)), unit(hash(self.seed, 2 * i as u64 + 1)))
+            },
+            SamplerKind::Jittered => {
+                let (col, row) = (i / self.rows, i % self.rows);
+                let u = (col as f64 + unit(hash(self.seed, 2 * i as u64))) / self.cols as f64;
+                let v = (row as f64 + unit(hash(self.seed, 2 * i as u64 + 1))) / self.rows as f64;
+                b.lerp(u, v)
+            },
+            SamplerKind::Halton => {
+                // Randomly shift the sequence by the seed, wrapping around the unit square.
+                let u = radical_inverse(i as u64 + 1, 2) + unit(hash(self.seed, 0));
+                let v = radical_inverse(i as u64 + 1, 3) + unit(hash(self.seed, 1));
+                b.lerp(u.fract(), v.fract())
+            },
+            SamplerKind::Sobol => {
+                // Scramble the sequence by XORing the bits with values derived from the seed.
+                let u = sobol(i as u64, 0) ^ hash(self.seed, 0);
+                let v = sobol(i as u64, 1) ^ hash(self.seed, 1);
+                b.lerp(unit(u), unit(v))
+            },
+        }
+    }
+}
+
+/// The number of steps of `delta` from `min` that stay below `max`.
+fn grid_steps(min: f64, max: f64, delta: f64) -> usize {
+    let below = |steps: usize| min + steps as f64 * delta < max;
+
+    // Estimate the count, then correct it for rounding.
+    let mut steps = ((max - min) / delta).ceil().max(0.0) as usize;
+    while steps > 0 && !below(steps - 1) {
+        steps -= 1;
+    }
+    while below(steps) {
+        steps += 1;
+    }
+
+    steps
+}
+
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// SplitMix64, used as a counter-based random number generator.
+fn hash(seed: u64, i: u64) -> u64 {
+    mix(seed.wrapping_add(i.wrapping_add(1).wrapping_mul(GOLDEN_GAMMA)))
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// A sequential random number generator for when samples aren't indexed, using SplitMix64.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64, stream: u64) -> Rng {
+        Rng { state: hash(seed, stream) }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(GOLDEN_GAMMA);
+        mix(self.state)
+    }
+
+    /// A random value in [0, 1).
+    pub fn next_f64(&mut self) -> f64 {
+        unit(self.next_u64())
+    }
+
+    /// A uniformly random point within `bounds`.
+    pub fn point_in(&mut self, bounds: &Bounds) -> Complex64 {
+        let u = self.next_f64();
+        let v = self.next_f64();
+        bounds.lerp(u, v)
+    }
+}
+
+/// Maps a random `u64` to [0, 1).
+fn unit(h: u64) -> f64 {
+    (h >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+fn radical_inverse(mut i: u64, base: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut factor = inv_base;
+    let mut res = 0.0;
+
+    while i > 0 {
+        res += (i % base) as f64 * factor;
+        i /= base;
+        factor *= inv_base;
+    }
+
+    res
+}
+
+/// The given dimension of the Sobol sequence, as a 64-bit fraction, so the sequence doesn't repeat
+/// until 2^64 samples.
+///
+/// The first dimension is the van der Corput sequence, and the second uses the primitive polynomial x + 1.
+fn sobol(i: u64, dim: usize) -> u64 {
+    let mut v = 1_u64 << 63;
+    let mut res = 0;
+    let mut i = i;
+
+    while i > 0 {
+        if i & 1 == 1 {
+            res ^= v;
+        }
+
+        v = if dim == 0 { v >> 1 } else { v ^ (v >> 1) };
+        i >>= 1;
+    }
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grid_steps, Bounds, Sampler, SamplerKind, Shard};
+    use region::{Region, Shape};
+
+    #[test]
+    fn shards_take_the_samples_between_them() {
+        let region = Region::new(Shape::Rect, Bounds::square(1.0));
+
+        for &(kind, samples) in &[(SamplerKind::Grid, None), (SamplerKind::Random, Some(1001)), (SamplerKind::Sobol, Some(1001))] {
+            let whole = Sampler::new(kind, &region, 0.1, samples, 7, Shard::whole());
+            let mut sharded = vec![];
+
+            for index in 1..4 {
+                let sampler = Sampler::new(kind, &region, 0.1, samples, 7, Shard { index, count: 3 });
+                sharded.extend((0..sampler.len()).map(|i| (i * 3 + index - 1, sampler.sample(i))));
+            }
+
+            sharded.sort_by_key(|s| s.0);
+            assert_eq!(sharded.len(), whole.len());
+            assert!(sharded.iter().enumerate().all(|(i, s)| s.0 == i && s.1 == whole.sample(i)));
+        }
+    }
+
+    #[test]
+    fn grid_steps_stay_below_the_maximum() {
+        for &(min, max, delta) in &[(-2.0, 2.0, 0.1), (-0.6, 0.6, 0.003), (0.0, 1.0, 0.25), (0.0, 1.0, 3.0), (1.0, 0.0, 0.1)] {
+            let stepped = (0_usize..).map(|x| min + x as f64 * delta).take_while(|&x| x < max).count();
+            assert_eq!(grid_steps(min, max, delta), stepped);
+        }
+    }
+
+    #[test]
+    fn jittered_renders_take_the_samples_asked_for() {
+        let region = Region::new(Shape::Rect, Bounds { re_min: -2.0, re_max: 1.0, im_min: -1.0, im_max: 1.0 });
+
+        for &n in &[1, 7, 100, 1001] {
+            assert_eq!(Sampler::new(SamplerKind::Jittered, &region, 0.1, Some(n), 7, Shard::whole()).len(), n);
+        }
+    }
+
+    #[test]
+    fn shards_are_parsed() {
+        assert_eq!("2/4".parse(), Ok(Shard { index: 2, count: 4 }));
+        assert_eq!(format!("{:?}", Shard { index: 2, count: 4 }), "2/4");
+        assert!("0/4".parse::<Shard>().is_err());
+        assert!("5/4".parse::<Shard>().is_err());
+        assert!("2".parse::<Shard>().is_err());
+    }
+
+    #[test]
+    fn shards_of_the_same_count_are_disjoint() {
+        let shard = |index, count| Shard { index, count };
+
+        assert!(!shard(1, 2).overlaps(&shard(2, 2)));
+        assert!(shard(1, 2).overlaps(&shard(1, 2)));
+        assert!(shard(1, 2).overlaps(&shard(2, 3)));
+    }
+}
@@ -0,0 +1,89 @@
+use num::complex::Complex64;
+
+use args::ParseError;
+
+use std::str::FromStr;
+
+/// An axis of the four-dimensional (z0, c) parameter space.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Axis {
+    Z0Re,
+    Z0Im,
+    CRe,
+    CIm,
+}
+
+const AXES: [Axis; 4] = [Axis::Z0Re, Axis::Z0Im, Axis::CRe, Axis::CIm];
+
+impl Axis {
+    fn index(&self) -> usize {
+        match *self {
+            Axis::Z0Re => 0,
+            Axis::Z0Im => 1,
+            Axis::CRe => 2,
+            Axis::CIm => 3,
+        }
+    }
+}
+
+impl FromStr for Axis {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Axis, ParseError> {
+        match s.trim().to_lowercase().as_str() {
+            "z0re" | "zr" => Ok(Axis::Z0Re),
+            "z0im" | "zi" => Ok(Axis::Z0Im),
+            "cre" | "cr" => Ok(Axis::CRe),
+            "cim" | "ci" => Ok(Axis::CIm),
+            _ => Err(format!("Unknown axis: {}", s).into()),
+        }
+    }
+}
+
+/// A plane through the (z0, c) space, which sample points are mapped onto.
+///
+/// The real and imaginary parts of a sample run along the two sampled axes, and the remaining axes
+/// are held at their fixed values. Rotating the plane by `angle` tilts each sampled axis towards one
+/// of the fixed axes, so a quarter turn from sampling c gives the Julia set for the fixed c.
+#[derive(Debug, Copy, Clone)]
+pub struct Slice {
+    pub sampled: (Axis, Axis),
+    /// Values of z0.re, z0.im, c.re and c.im, where the sampled axes are offsets from them.
+    pub origin: [f64; 4],
+    /// Rotation of the plane, in radians.
+    pub angle: f64,
+}
+
+impl Slice {
+    /// The two axes that aren't sampled, in order.
+    fn fixed(&self) -> (Axis, Axis) {
+        let mut fixed = AXES.iter().filter(|&&a| a != self.sampled.0 && a != self.sampled.1);
+        (*fixed.next().unwrap(), *fixed.next().unwrap())
+    }
+
+    /// The (z0, c) pair for a sample point.
+    pub fn point(&self, s: Complex64) -> (Complex64, Complex64) {
+        let mut p = self.origin;
+        let (fixed_u, fixed_v) = self.fixed();
+        let (cos, sin) = (self.angle.cos(), self.angle.sin());
+
+        p[self.sampled.0.index()] += s.re * cos;
+        p[fixed_u.index()] += s.re * sin;
+        p[self.sampled.1.index()] += s.im * cos;
+        p[fixed_v.index()] += s.im * sin;
+
+        (Complex64::new(p[0], p[1]), Complex64::new(p[2], p[3]))
+    }
+
+    /// The derivatives of z0 and c with respect to the sample point, when the slice is a complex
+    /// plane tilted between the z0 and c planes, so they can be used for distance estimation.
+    pub fn derivatives(&self) -> Option<(Complex64, Complex64)> {
+        let (cos, sin) = (Complex64::new(self.angle.cos(), 0.0), Complex64::new(self.angle.sin(), 0.0));
+
+        match self.sampled {
+            (Axis::CRe, Axis::CIm) => Some((sin, cos)),
+            (Axis::Z0Re, Axis::Z0Im) => Some((cos, sin)),
+            _ => None,
+        }
+    }
+}
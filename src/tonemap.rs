@@ -0,0 +1,285 @@
+use image::{ImageBuffer, Rgba};
+
+use args::ParseError;
+use canvas::{Accumulator, Canvas};
+use palette::Palette;
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// How accumulated densities are mapped to brightness.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Curve {
+    /// Proportional to the density, with the brightest pixel white.
+    Linear,
+    /// The logarithm of the density, with the brightest pixel white.
+    Log,
+    /// The density raised to 1/gamma, with the brightest pixel white.
+    Gamma(f64),
+    /// `1 - exp(-p/factor)`, using the channel's brightness factor.
+    Exp,
+    /// The fraction of lit pixels that are no brighter, so brightness levels are evenly used.
+    Equalise,
+    /// Linear up to the given percentile of lit pixels, which becomes white.
+    Clip(f64),
+}
+
+impl FromStr for Curve {
+    type Err = ParseError;
+
+    /// Parses a curve name. Gamma and clip optionally take their parameter after a colon, e.g. `gamma:2.2` or `clip:99.5`.
+    fn from_str(s: &str) -> Result<Curve, ParseError> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("").trim().to_lowercase();
+        let arg = parts.next();
+
+        let param = |a: &str| match a.trim().parse() {
+            Ok(v) if v > 0.0 => Ok(v),
+            _ => Err(format!("Invalid {} parameter: {}", name, a)),
+        };
+
+        match (name.as_str(), arg) {
+            ("linear", None) => Ok(Curve::Linear),
+            ("log", None) => Ok(Curve::Log),
+            ("sqrt", None) => Ok(Curve::Gamma(2.0)),
+            ("gamma", None) => Ok(Curve::Gamma(2.2)),
+            ("gamma", Some(a)) => Ok(Curve::Gamma(param(a)?)),
+            ("exp", None) => Ok(Curve::Exp),
+            ("equalise", None) | ("equalize", None) | ("histogram", None) => Ok(Curve::Equalise),
+            ("clip", None) => Ok(Curve::Clip(99.5)),
+            ("clip", Some(a)) => match param(a)? {
+                pct if pct <= 100.0 => Ok(Curve::Clip(pct)),
+                pct => Err(format!("Invalid clip percentile: {}", pct).into()),
+            },
+            _ => Err(format!("Unknown curve: {}", s).into()),
+        }
+    }
+}
+
+impl fmt::Display for Curve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Curve::Linear => write!(f, "linear"),
+            Curve::Log => write!(f, "log"),
+            Curve::Gamma(g) => write!(f, "gamma:{}", g),
+            Curve::Exp => write!(f, "exp"),
+            Curve::Equalise => write!(f, "equalise"),
+            Curve::Clip(pct) => write!(f, "clip:{}", pct),
+        }
+    }
+}
+
+impl Curve {
+    /// The next curve in turn, for cycling through them in the viewer.
+    pub fn next(&self) -> Curve {
+        match *self {
+            Curve::Linear => Curve::Log,
+            Curve::Log => Curve::Gamma(2.2),
+            Curve::Gamma(_) => Curve::Exp,
+            Curve::Exp => Curve::Equalise,
+            Curve::Equalise => Curve::Clip(99.5),
+            Curve::Clip(_) => Curve::Linear,
+        }
+    }
+}
+
+/// The non-zero values of one channel, in ascending order.
+pub fn sorted_channel<T: Accumulator>(canvas: &Canvas<T>, channel: usize) -> Vec<f64> {
+    let mut values: Vec<f64> = canvas.values().chunks(canvas.channels())
+        .map(|px| px[channel].to_f64())
+        .filter(|&p| p > 0.0)
+        .collect();
+
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    values
+}
+
+/// The value below which `pct` percent of `sorted` falls, or zero if it's empty.
+pub fn percentile(sorted: &[f64], pct: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    let idx = ((pct / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    sorted[idx.min(sorted.len() - 1)]
+}
+
+/// Brightness given to the auto-exposure percentile by the exp curve.
+const EXPOSURE_WHITE: f64 = 0.98;
+
+/// The exp curve factor that maps the given percentile of a channel's lit pixels to near-white,
+/// or `None` if the channel is empty.
+pub fn auto_factor<T: Accumulator>(canvas: &Canvas<T>, channel: usize, pct: f64) -> Option<f64> {
+    let p = percentile(&sorted_channel(canvas, channel), pct);
+
+    if p > 0.0 {
+        Some(p / -(1.0 - EXPOSURE_WHITE).ln())
+    } else {
+        None
+    }
+}
+
+/// The exp curve factor that matches blending each point with opacity `o`.
+///
+/// Blending a point with opacity `o` n times gives a brightness of 1 - (1-o)^n,
+/// which is the exponential curve with a factor of -1/ln(1-o).
+pub fn opacity_factor(o: f64) -> f64 {
+    -1.0 / (1.0 - o).ln()
+}
+
+/// The channel factors, with each replaced by the one `auto_factor` derives from the canvas when
+/// auto-exposure is on.
+pub fn exposed_factors<T: Accumulator>(canvas: &Canvas<T>, factors: &[f64], auto_exposure: Option<f64>) -> Vec<f64> {
+    match auto_exposure {
+        Some(pct) => factors.iter().enumerate()
+            .map(|(ch, &factor)| auto_factor(canvas, ch, pct).unwrap_or(factor))
+            .collect(),
+        None => factors.to_vec(),
+    }
+}
+
+/// Checks the auto-exposure percentile, if any, is between 0 and 100.
+pub fn check_auto_exposure(auto_exposure: Option<f64>) -> Result<(), String> {
+    match auto_exposure {
+        Some(pct) if !(pct > 0.0 && pct <= 100.0) => Err("The auto-exposure percentile must be between 0 and 100.".to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// A curve prepared with the statistics of one channel of a canvas.
+pub struct ToneMap {
+    curve: Curve,
+    factor: f64,
+    /// Scales densities before they're mapped, so above 1 brightens with any curve.
+    exposure: f64,
+    white: f64,
+    /// The fraction of lit pixels that the equalise curve maps to white.
+    white_rank: f64,
+    sorted: Vec<f64>,
+}
+
+impl ToneMap {
+    /// Prepares `curve` for a channel. With `auto_exposure`, that percentile of lit pixels becomes
+    /// white rather than the brightest pixel, except with the exp curve, whose factor is set by
+    /// `auto_factor` instead, and the clip curve, which has its own percentile.
+    ///
+    /// Densities are multiplied by `exposure` before they're mapped, with the white point unchanged.
+    pub fn new<T: Accumulator>(canvas: &Canvas<T>, channel: usize, curve: Curve, factor: f64, auto_exposure: Option<f64>, exposure: f64) -> ToneMap {
+        let auto_exposure = match curve {
+            Curve::Exp | Curve::Clip(_) => None,
+            _ => auto_exposure,
+        };
+
+        let sorted = match curve {
+            Curve::Equalise | Curve::Clip(_) => sorted_channel(canvas, channel),
+            _ if auto_exposure.is_some() => sorted_channel(canvas, channel),
+            _ => vec![],
+        };
+
+        let white = match (curve, auto_exposure) {
+            (Curve::Clip(pct), _) | (_, Some(pct)) => percentile(&sorted, pct),
+            _ => canvas.values().chunks(canvas.channels())
+                .map(|px| px[channel].to_f64())
+                .fold(0.0, f64::max),
+        };
+
+        ToneMap {
+            curve,
+            factor,
+            exposure,
+            white,
+            white_rank: auto_exposure.map_or(1.0, |pct| pct / 100.0),
+            sorted,
+        }
+    }
+
+    /// Maps a density to a brightness between 0 and 1.
+    pub fn map(&self, p: f64) -> f64 {
+        if p <= 0.0 {
+            return 0.0;
+        }
+
+        let p = p * self.exposure;
+
+        let val = match self.curve {
+            Curve::Linear | Curve::Clip(_) => p / self.white,
+            Curve::Log => (1.0 + p).ln() / (1.0 + self.white).ln(),
+            Curve::Gamma(g) => (p / self.white).powf(1.0 / g),
+            Curve::Exp => 1.0 - (-p / self.factor).exp(),
+            Curve::Equalise => {
+                // The number of values no greater than p.
+                let rank = match self.sorted.binary_search_by(|v| if *v <= p { Ordering::Less } else { Ordering::Greater }) {
+                    Ok(i) | Err(i) => i,
+                };
+                rank as f64 / self.sorted.len() as f64 / self.white_rank
+            },
+        };
+
+        val.min(1.0)
+    }
+}
+
+/// Maps the canvas to red, green and blue brightness from 0 to 1, in row order, with each channel
+/// mapped by `curve` and its factor, `auto_exposure` as the white point, and densities scaled by
+/// `exposure`.
+///
+/// A single channel is coloured with `palette`, or greyscale without one.
+pub fn map<T: Accumulator>(canvas: &Canvas<T>, curve: Curve, factors: &[f64], auto_exposure: Option<f64>, exposure: f64, palette: Option<&Palette>) -> Vec<[f64; 3]> {
+    let maps: Vec<_> = factors.iter().enumerate()
+        .map(|(ch, &factor)| ToneMap::new(canvas, ch, curve, factor, auto_exposure, exposure))
+        .collect();
+
+    let mut rgb = Vec::with_capacity((canvas.width() * canvas.height()) as usize);
+    for y in 0..canvas.height() {
+        for x in 0..canvas.width() {
+            let mut px = [0.0; 3];
+
+            for (ch, map) in maps.iter().enumerate() {
+                px[ch] = map.map(canvas.get(x, y, ch).to_f64());
+            }
+
+            if maps.len() == 1 {
+                px = match palette {
+                    Some(palette) => palette.colour(px[0]),
+                    None => [px[0]; 3],
+                };
+            }
+
+            rgb.push(px);
+        }
+    }
+
+    rgb
+}
+
+/// Draws the canvas into an image, mapping each channel with `curve` and its factor,
+/// `auto_exposure` as the white point, and densities scaled by `exposure`.
+///
+/// A single channel is coloured with `palette`, or drawn in greyscale without one.
+pub fn draw<T: Accumulator>(canvas: &Canvas<T>, curve: Curve, factors: &[f64], auto_exposure: Option<f64>, exposure: f64, palette: Option<&Palette>, to: &mut ImageBuffer<Rgba<u8>, Vec<u8>>) {
+    let rgb = map(canvas, curve, factors, auto_exposure, exposure, palette);
+
+    for (p2, px) in to.pixels_mut().zip(rgb.iter()) {
+        *p2 = Rgba([(px[0] * 255.0) as u8, (px[1] * 255.0) as u8, (px[2] * 255.0) as u8, 255]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Curve, ToneMap};
+    use canvas::Canvas;
+
+    #[test]
+    fn exposure_brightens_every_curve() {
+        let canvas = Canvas::from_values(4, 1, 1, vec![1_u32, 2, 4, 8], 0);
+
+        for &curve in &[Curve::Linear, Curve::Log, Curve::Gamma(2.2), Curve::Exp, Curve::Equalise, Curve::Clip(99.5)] {
+            let normal = ToneMap::new(&canvas, 0, curve, 5.0, None, 1.0);
+            let brighter = ToneMap::new(&canvas, 0, curve, 5.0, None, 2.0);
+
+            assert!(brighter.map(2.0) > normal.map(2.0), "{} isn't brightened", curve);
+            assert_eq!(brighter.map(0.0), 0.0);
+        }
+    }
+}
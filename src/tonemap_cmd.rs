@@ -0,0 +1,112 @@
+extern crate rpixi;
+use rpixi::args::List;
+use rpixi::canvas::{Accumulator, AccumulatorKind};
+use rpixi::output::{self, Samples};
+use rpixi::overlay::Overlay;
+use rpixi::palette::Palette;
+use rpixi::raw;
+use rpixi::tonemap::{self, Curve};
+
+extern crate structopt;
+#[macro_use]
+extern crate structopt_derive;
+use structopt::StructOpt;
+
+/// Tone-maps a raw canvas saved by `cmdline --raw`, without rendering it again.
+#[derive(Debug, StructOpt)]
+struct Config {
+    #[structopt(help="Raw canvas file to tone-map")]
+    input: String,
+
+    #[structopt(short="o", help="Opacity of the drawn pixel", default_value = "0.8")]
+    opacity: f64,
+
+    #[structopt(long="curve", help="Tone curve: linear, log, sqrt, gamma[:g], exp, equalise or clip[:percentile]", default_value = "exp")]
+    curve: Curve,
+
+    #[structopt(long="palette", help="Colour a single channel with a preset (grey, fire, ice, nebula, viridis) or a .csv or .ggr gradient file")]
+    palette: Option<Palette>,
+
+    #[structopt(long="auto-exposure", help="Map this percentile of lit pixels to white, or near-white with the exp curve, e.g. 99.5")]
+    auto_exposure: Option<f64>,
+
+    #[structopt(long="channel-factors", help="Comma-separated brightness exponents for each channel")]
+    channel_factors: Option<List<f64>>,
+
+    #[structopt(long="output", help="Image to save, as .png, .tiff, .ppm or .bmp", default_value = "out.png")]
+    output: String,
+
+    #[structopt(long="bit-depth", help="Bits per sample, 8 or 16; 16-bit images are saved without the text overlay", default_value = "8")]
+    bit_depth: u8,
+
+    #[structopt(long="no-overlay", help="Don't draw the render's configuration over the image")]
+    no_overlay: bool,
+}
+
+impl Config {
+    fn channel_factors(&self, channels: usize) -> Vec<f64> {
+        match self.channel_factors {
+            Some(ref factors) => factors.0.clone(),
+            None => vec![tonemap::opacity_factor(self.opacity); channels],
+        }
+    }
+}
+
+fn run<T: Accumulator>(cfg: &Config) -> Result<(), String> {
+    let (header, canvas) = raw::open::<T>(&cfg.input)?;
+
+    let factors = cfg.channel_factors(canvas.channels());
+    if factors.len() != canvas.channels() {
+        return Err(format!("Expected one factor for each of the {} channels.", canvas.channels()));
+    }
+
+    if cfg.palette.is_some() && canvas.channels() != 1 {
+        return Err("Palettes only apply to a single channel.".to_string());
+    }
+
+    let factors = tonemap::exposed_factors(&canvas, &factors, cfg.auto_exposure);
+    if cfg.auto_exposure.is_some() && cfg.curve == Curve::Exp {
+        println!("Factors: {:?}", factors);
+    }
+    let rgb = tonemap::map(&canvas, cfg.curve, &factors, cfg.auto_exposure, 1.0, cfg.palette.as_ref());
+
+    if cfg.bit_depth == 16 {
+        return output::save(&cfg.output, header.width, header.height, Samples::U16(&output::to_u16(&rgb)));
+    }
+
+    let mut buffer = output::to_image(header.width, header.height, &rgb);
+
+    if !cfg.no_overlay {
+        let mut status = cfg.input.clone();
+        if header.overflowed > 0 {
+            status += &format!(" - {} overflowed", header.overflowed);
+        }
+
+        Overlay::new().draw(&mut buffer, &status, &header.config);
+    }
+
+    output::save(&cfg.output, header.width, header.height, Samples::U8(&output::rgb_bytes(&buffer)))
+}
+
+/// Checks the options, then tone-maps with the accumulator the canvas was saved with.
+fn tone_map(cfg: &Config) -> Result<(), String> {
+    output::check_bit_depth(&cfg.output, cfg.bit_depth)?;
+    tonemap::check_auto_exposure(cfg.auto_exposure)?;
+
+    match raw::open_header(&cfg.input)?.kind {
+        AccumulatorKind::U16 => run::<u16>(cfg),
+        AccumulatorKind::U32 => run::<u32>(cfg),
+        AccumulatorKind::U64 => run::<u64>(cfg),
+        AccumulatorKind::F32 => run::<f32>(cfg),
+        AccumulatorKind::F64 => run::<f64>(cfg),
+    }
+}
+
+fn main() {
+    let cfg = Config::from_args();
+
+    if let Err(e) = tone_map(&cfg) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,155 @@
+use num::complex::Complex64;
+
+use args::ParseError;
+
+use std::str::FromStr;
+
+/// Which axis of the plane runs horizontally across the image.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Axes {
+    /// Real axis horizontal, imaginary axis vertical.
+    ReIm,
+    /// Imaginary axis horizontal, real axis vertical, as Buddhabrots are often shown.
+    ImRe,
+}
+
+impl FromStr for Axes {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Axes, ParseError> {
+        match s.trim().to_lowercase().as_str() {
+            "re-im" => Ok(Axes::ReIm),
+            "im-re" => Ok(Axes::ImRe),
+            _ => Err(format!("Unknown axes: {}", s).into()),
+        }
+    }
+}
+
+/// Maps points on the complex plane to pixels of the output image.
+#[derive(Debug, Copy, Clone)]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+    /// Pixels per unit on the plane.
+    pub zoom: f64,
+    /// The point shown at the centre of the image.
+    pub centre: Complex64,
+    /// Anticlockwise rotation of the view, in radians.
+    pub rotation: f64,
+    pub axes: Axes,
+}
+
+impl Viewport {
+    pub fn new(width: u32, height: u32, zoom: f64) -> Viewport {
+        Viewport {
+            width,
+            height,
+            zoom,
+            centre: Complex64::new(0.0, 0.0),
+            rotation: 0.0,
+            axes: Axes::ReIm,
+        }
+    }
+
+    /// The continuous pixel position of `z`, where pixel (x, y) covers [x, x+1) by [y, y+1).
+    pub fn position(&self, z: Complex64) -> (f64, f64) {
+        let mut w = z - self.centre;
+        if self.rotation != 0.0 {
+            w *= Complex64::from_polar(&1.0, &-self.rotation);
+        }
+
+        let (u, v) = match self.axes {
+            Axes::ReIm => (w.re, w.im),
+            Axes::ImRe => (w.im, w.re),
+        };
+
+        let pos_x = (self.width as f64/2.0) + u * self.zoom;
+        let pos_y = (self.height as f64/2.0) - v * self.zoom;
+
+        (pos_x, pos_y)
+    }
+
+    /// The point at the centre of pixel (x, y), the inverse of `position`.
+    pub fn unproject(&self, x: u32, y: u32) -> Complex64 {
+        let u = (x as f64 + 0.5 - self.width as f64/2.0) / self.zoom;
+        let v = (self.height as f64/2.0 - (y as f64 + 0.5)) / self.zoom;
+
+        let mut w = match self.axes {
+            Axes::ReIm => Complex64::new(u, v),
+            Axes::ImRe => Complex64::new(v, u),
+        };
+        if self.rotation != 0.0 {
+            w *= Complex64::from_polar(&1.0, &self.rotation);
+        }
+
+        w + self.centre
+    }
+
+    /// Returns the pixel containing `z`, or `None` if it falls outside the image.
+    pub fn project(&self, z: Complex64) -> Option<(u32, u32)> {
+        let (pos_x, pos_y) = self.position(z);
+
+        if !(pos_x >= 0.0 && pos_y >= 0.0 && pos_x < self.width as f64 && pos_y < self.height as f64) {
+            return None;
+        }
+
+        Some((pos_x as u32, pos_y as u32))
+    }
+
+    /// The number of points that land inside the image.
+    pub fn visible(&self, points: &[Complex64]) -> usize {
+        points.iter().filter(|&&z| self.project(z).is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Axes, Viewport};
+    use num::complex::Complex64;
+
+    fn views() -> Vec<Viewport> {
+        let mut views = vec![];
+
+        for &axes in &[Axes::ReIm, Axes::ImRe] {
+            for &rotation in &[0.0, 0.3, -2.0] {
+                views.push(Viewport {
+                    centre: Complex64::new(-0.5, 0.25),
+                    rotation,
+                    axes,
+                    ..Viewport::new(40, 30, 12.5)
+                });
+            }
+        }
+
+        views
+    }
+
+    #[test]
+    fn unprojecting_gives_pixel_centres() {
+        for view in views() {
+            for &(x, y) in &[(0, 0), (39, 29), (20, 15), (7, 22)] {
+                let (pos_x, pos_y) = view.position(view.unproject(x, y));
+                assert!((pos_x - (x as f64 + 0.5)).abs() < 1e-9 && (pos_y - (y as f64 + 0.5)).abs() < 1e-9);
+                assert_eq!(view.project(view.unproject(x, y)), Some((x, y)));
+            }
+        }
+    }
+
+    #[test]
+    fn the_centre_is_shown_in_the_middle() {
+        for view in views() {
+            assert_eq!(view.position(view.centre), (20.0, 15.0));
+        }
+    }
+
+    #[test]
+    fn axes_swap_the_directions_of_the_plane() {
+        let view = Viewport::new(40, 30, 10.0);
+        let flipped = Viewport { axes: Axes::ImRe, ..view };
+        let z = Complex64::new(1.0, 0.5);
+
+        assert_eq!(view.position(z), (30.0, 10.0));
+        assert_eq!(flipped.position(z), (25.0, 5.0));
+        assert_eq!(view.project(Complex64::new(3.0, 0.0)), None);
+    }
+}